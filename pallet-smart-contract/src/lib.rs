@@ -1,11 +1,17 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+use codec::Encode;
 use frame_support::{
     debug, decl_error, decl_event, decl_module, decl_storage, ensure,
-    traits::{Currency, ExistenceRequirement::KeepAlive, Vec, Get},
+    traits::{Currency, EnsureOrigin, ExistenceRequirement::KeepAlive, Vec, Get},
+    weights::Weight,
 };
 use frame_system::{self as system, ensure_signed};
-use sp_runtime::{traits::SaturatedConversion, DispatchError, DispatchResult, Perbill};
+use sp_std::vec;
+use sp_runtime::{
+    traits::{SaturatedConversion, Saturating, Zero},
+    DispatchError, DispatchResult, Perbill,
+};
 
 use pallet_tfgrid;
 use pallet_tfgrid::types as pallet_tfgrid_types;
@@ -26,9 +32,11 @@ pub trait Config: system::Config + pallet_tfgrid::Config + pallet_timestamp::Con
     type Currency: Currency<Self::AccountId>;
     type StakingPoolAccount: Get<Self::AccountId>;
     type BillingFrequency: Get<u64>;
+    type DeploymentTimeout: Get<u64>;
+    type GracePeriod: Get<u64>;
 }
 
-pub const CONTRACT_VERSION: u32 = 1;
+pub const CONTRACT_VERSION: u32 = 2;
 
 pub type BalanceOf<T> =
     <<T as Config>::Currency as Currency<<T as system::Config>::AccountId>>::Balance;
@@ -42,9 +50,25 @@ decl_event!(
         ContractCreated(types::Contract),
         ContractUpdated(types::Contract),
         NodeContractCanceled(u64, u32, u32),
+        NodeContractCanceledByNode(u64, u32, u32, types::Cause),
+        ContractGracePeriodStarted(u64, u32, u32, u64),
+        ContractGracePeriodEnded(u64, u32, u32),
+        DebtIncurred(u64, u32, BalanceOf),
+        DebtSettled(u64, u32, BalanceOf),
         NameContractCanceled(u64),
+        DomainContractCanceled(u64),
+        NameContractTransferOffered(u64, u32, u32),
+        NameContractTransferCanceled(u64),
+        NameContractTransferred(u64, u32, u32),
+        ReservedNameAdded(Vec<u8>),
+        ReservedNameRemoved(Vec<u8>),
+        RevenueDistributionSet(Vec<types::DistributionShare>),
+        DiscountConfigurationSet(types::DiscountConfiguration),
+        NameContractPricingPolicySet(u32),
         IPsReserved(u64, Vec<pallet_tfgrid_types::PublicIP>),
         IPsFreed(u64, Vec<Vec<u8>>),
+        IPv6Reserved(u64, Vec<Vec<u8>>),
+        IPv6Freed(u64, Vec<Vec<u8>>),
         ContractDeployed(u64, AccountId),
         ConsumptionReportReceived(types::Consumption),
         ContractBilled(types::ContractBill),
@@ -60,19 +84,48 @@ decl_error! {
         FarmNotExists,
         FarmHasNotEnoughPublicIPs,
         FarmHasNotEnoughPublicIPsFree,
+        FarmHasNotEnoughPublicIPv6Free,
+        InvalidIPv6PrefixLength,
+        NodeContractHasIPv6,
+        TooManyRequestedIPs,
+        RequestedIPNotAvailable,
         FailedToReserveIP,
         FailedToFreeIPs,
         ContractNotExists,
         TwinNotAuthorizedToUpdateContract,
         TwinNotAuthorizedToCancelContract,
+        TwinNotAuthorizedToCreateContract,
         NodeNotAuthorizedToDeployContract,
+        ContractNotAwaitingDeployment,
+        NodeNotAuthorizedToCancelContract,
+        InvalidCancelCause,
+        ContractIsCanceled,
+        ContractInGracePeriod,
+        TwinHasDebt,
         NodeNotAuthorizedToComputeReport,
         PricingPolicyNotExists,
         ContractIsNotUnique,
         NameExists,
         NameNotValid,
+        NameTooShort,
+        NameTooLong,
+        NameHasLeadingOrTrailingHyphen,
+        NameReserved,
+        NameNotReserved,
+        NameContractTransferToSameTwin,
+        NameContractTransferNotOffered,
+        NameContractTransferNotAuthorized,
+        RevenueDistributionNotComplete,
+        RevenueDistributionDuplicateDestination,
+        DiscountTiersNotAscending,
+        DiscountTierLevelNotValid,
+        GatewayNotExists,
+        GatewayNotAuthorizedToVerifyDomain,
+        DomainExists,
+        DomainNotValid,
         InvalidContractType,
-        TFTPriceValueError
+        TFTPriceValueError,
+        NodeNotAvailableToDeploy
     }
 }
 
@@ -88,7 +141,28 @@ decl_storage! {
         
         pub ActiveNodeContracts get(fn active_node_contracts): map hasher(blake2_128_concat) u32 => Vec<u64>;
         pub ContractsToBillAt get(fn contract_to_bill_at_block): map hasher(blake2_128_concat) u64 => Vec<u64>;
+        // the block at which a contract is billed next
+        pub ContractNextBillingBlock get(fn contract_next_billing_block): map hasher(blake2_128_concat) u64 => u64;
+        // node contracts that are canceled at a block if their node did not confirm the deployment by then
+        pub ContractsToDeployBy get(fn contracts_to_deploy_by_block): map hasher(blake2_128_concat) u64 => Vec<u64>;
         pub ContractIDByNameRegistration get(fn contract_id_by_name_registration): map hasher(blake2_128_concat) Vec<u8> => u64;
+        pub ContractIDByDomain get(fn contract_id_by_domain): map hasher(blake2_128_concat) Vec<u8> => u64;
+        // pending name contract transfers, contract id => id of the twin the name is offered to
+        pub NameContractTransferOffers get(fn name_contract_transfer_offers): map hasher(blake2_128_concat) u64 => u32;
+        // names that cannot be registered with a name contract, managed by governance
+        pub ReservedNames get(fn reserved_names): map hasher(blake2_128_concat) Vec<u8> => bool;
+        // how every billed amount is split, whatever is not sent to an account is burned
+        pub RevenueDistribution get(fn revenue_distribution): Vec<types::DistributionShare> = types::default_revenue_distribution();
+        // the discount tiers and the certified capacity surcharge applied when billing contracts
+        pub DiscountConfiguration get(fn discount_configuration): types::DiscountConfiguration = types::default_discount_configuration();
+
+        // the part of a contract's bill that its twin could not pay, by twin id and contract id
+        pub ContractDebt get(fn contract_debt): double_map hasher(blake2_128_concat) u32, hasher(blake2_128_concat) u64 => BalanceOf<T>;
+        // the total outstanding debt of a twin over all its contracts
+        pub TwinDebt get(fn twin_debt): map hasher(blake2_128_concat) u32 => BalanceOf<T>;
+
+        // the pricing policy new name contracts are billed with
+        pub NameContractPricingPolicyID get(fn name_contract_pricing_policy_id): u32 = 1;
 
         // ID maps
         ContractID: u64;
@@ -96,6 +170,13 @@ decl_storage! {
         /// The current version of the pallet.
         PalletVersion: types::PalletStorageVersion = types::PalletStorageVersion::V1;
     }
+
+    add_extra_genesis {
+        build(|_config| {
+            // a new chain starts with the latest storage layout
+            PalletVersion::put(types::PalletStorageVersion::V4);
+        });
+    }
 }
 
 decl_module! {
@@ -103,15 +184,31 @@ decl_module! {
         fn deposit_event() = default;
 
         #[weight = 10]
-        fn create_node_contract(origin, node_id: u32, data: Vec<u8>, deployment_hash: Vec<u8>, public_ips: u32){
+        fn create_node_contract(origin, node_id: u32, data: Vec<u8>, deployment_hash: Vec<u8>, public_ips: u32, requested_ips: Vec<Vec<u8>>){
+            let account_id = ensure_signed(origin)?;
+            let twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_create_node_contract(twin_id, node_id, data, deployment_hash, public_ips, requested_ips)?;
+        }
+
+        #[weight = 10]
+        fn create_node_contract_for_twin(origin, twin_id: u32, node_id: u32, data: Vec<u8>, deployment_hash: Vec<u8>, public_ips: u32, requested_ips: Vec<Vec<u8>>){
+            let account_id = ensure_signed(origin)?;
+            Self::_ensure_authorized_to_create_contract(&account_id, twin_id)?;
+            Self::_create_node_contract(twin_id, node_id, data, deployment_hash, public_ips, requested_ips)?;
+        }
+
+        // Reserves a public ipv6 allocation with the given prefix length, eg. 64, on the farm
+        // of the node for a node contract that has none yet
+        #[weight = 10]
+        fn reserve_node_contract_ipv6(origin, contract_id: u64, prefix_length: u8){
             let account_id = ensure_signed(origin)?;
-            Self::_create_node_contract(account_id, node_id, data, deployment_hash, public_ips)?;
+            Self::_reserve_node_contract_ipv6(account_id, contract_id, prefix_length)?;
         }
 
         #[weight = 10]
-        fn update_node_contract(origin, contract_id: u64, data: Vec<u8>, deployment_hash: Vec<u8>){
+        fn update_node_contract(origin, contract_id: u64, data: Vec<u8>, deployment_hash: Vec<u8>, public_ips: Option<u32>, requested_ips: Vec<Vec<u8>>){
             let account_id = ensure_signed(origin)?;
-            Self::_update_node_contract(account_id, contract_id, data, deployment_hash)?;
+            Self::_update_node_contract(account_id, contract_id, data, deployment_hash, public_ips, requested_ips)?;
         }
 
         #[weight = 10]
@@ -120,6 +217,31 @@ decl_module! {
             Self::_cancel_contract(account_id, contract_id, types::Cause::CanceledByUser)?;
         }
 
+        #[weight = 10]
+        fn cancel_contract_by_node(origin, contract_id: u64, cause: types::Cause){
+            let account_id = ensure_signed(origin)?;
+            Self::_cancel_contract_by_node(account_id, contract_id, cause)?;
+        }
+
+        #[weight = 10]
+        fn deploy_contract(origin, contract_id: u64){
+            let account_id = ensure_signed(origin)?;
+            Self::_deploy_contract(account_id, contract_id)?;
+        }
+
+        #[weight = 10]
+        fn reject_contract(origin, contract_id: u64){
+            let account_id = ensure_signed(origin)?;
+            Self::_reject_contract(account_id, contract_id)?;
+        }
+
+        #[weight = 10]
+        fn settle_debt(origin){
+            let account_id = ensure_signed(origin)?;
+            let twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_settle_debt(twin_id)?;
+        }
+
         #[weight = 10]
         fn add_reports(origin, reports: Vec<types::Consumption>) {
             let account_id = ensure_signed(origin)?;
@@ -129,7 +251,138 @@ decl_module! {
         #[weight = 10]
         fn create_name_contract(origin, name: Vec<u8>) {
             let account_id = ensure_signed(origin)?;
-            Self::_create_name_contract(account_id, name)?;
+            let twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_create_name_contract(twin_id, name)?;
+        }
+
+        #[weight = 10]
+        fn create_name_contract_for_twin(origin, twin_id: u32, name: Vec<u8>) {
+            let account_id = ensure_signed(origin)?;
+            Self::_ensure_authorized_to_create_contract(&account_id, twin_id)?;
+            Self::_create_name_contract(twin_id, name)?;
+        }
+
+        #[weight = 10]
+        fn create_domain_contract(origin, gateway_id: u32, domain: Vec<u8>) {
+            let account_id = ensure_signed(origin)?;
+            let twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_create_domain_contract(twin_id, gateway_id, domain)?;
+        }
+
+        #[weight = 10]
+        fn create_domain_contract_for_twin(origin, twin_id: u32, gateway_id: u32, domain: Vec<u8>) {
+            let account_id = ensure_signed(origin)?;
+            Self::_ensure_authorized_to_create_contract(&account_id, twin_id)?;
+            Self::_create_domain_contract(twin_id, gateway_id, domain)?;
+        }
+
+        #[weight = 10]
+        fn verify_domain_contract(origin, contract_id: u64) {
+            let account_id = ensure_signed(origin)?;
+            Self::_verify_domain_contract(account_id, contract_id)?;
+        }
+
+        // The twin owning a name contract offers it to another twin, the transfer only happens
+        // once the receiving twin accepts it
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn offer_name_contract_transfer(origin, contract_id: u64, twin_id: u32) {
+            let account_id = ensure_signed(origin)?;
+            let owner_twin_id = Self::_get_twin_id(&account_id)?;
+            let contract = Self::_get_name_contract_of_twin(contract_id, owner_twin_id)?;
+
+            ensure!(
+                pallet_tfgrid::Twins::<T>::contains_key(twin_id),
+                Error::<T>::TwinNotExists
+            );
+            ensure!(twin_id != contract.twin_id, Error::<T>::NameContractTransferToSameTwin);
+
+            // a new offer replaces any pending one
+            NameContractTransferOffers::insert(contract_id, twin_id);
+
+            Self::deposit_event(RawEvent::NameContractTransferOffered(contract_id, owner_twin_id, twin_id));
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn cancel_name_contract_transfer(origin, contract_id: u64) {
+            let account_id = ensure_signed(origin)?;
+            let owner_twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_get_name_contract_of_twin(contract_id, owner_twin_id)?;
+            ensure!(
+                NameContractTransferOffers::contains_key(contract_id),
+                Error::<T>::NameContractTransferNotOffered
+            );
+
+            NameContractTransferOffers::remove(contract_id);
+
+            Self::deposit_event(RawEvent::NameContractTransferCanceled(contract_id));
+        }
+
+        // The receiving twin accepts a name contract transfer, from then on it is billed for the name
+        #[weight = 10]
+        fn accept_name_contract_transfer(origin, contract_id: u64) {
+            let account_id = ensure_signed(origin)?;
+            let twin_id = Self::_get_twin_id(&account_id)?;
+            Self::_accept_name_contract_transfer(twin_id, contract_id)?;
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn set_name_contract_pricing_policy(origin, pricing_policy_id: u32) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            ensure!(
+                pallet_tfgrid::PricingPolicies::<T>::contains_key(pricing_policy_id),
+                Error::<T>::PricingPolicyNotExists
+            );
+            NameContractPricingPolicyID::put(pricing_policy_id);
+
+            Self::deposit_event(RawEvent::NameContractPricingPolicySet(pricing_policy_id));
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn update_name_contract_pricing_policy(origin, contract_id: u64, pricing_policy_id: u32) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            Self::_update_name_contract_pricing_policy(contract_id, pricing_policy_id)?;
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn add_reserved_name(origin, name: Vec<u8>) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            let name = Self::_normalize_name(name);
+            Self::_validate_dns_label(&name)?;
+            ReservedNames::insert(&name, true);
+            Self::deposit_event(RawEvent::ReservedNameAdded(name));
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn remove_reserved_name(origin, name: Vec<u8>) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            let name = Self::_normalize_name(name);
+            ensure!(ReservedNames::contains_key(&name), Error::<T>::NameNotReserved);
+            ReservedNames::remove(&name);
+            Self::deposit_event(RawEvent::ReservedNameRemoved(name));
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn set_revenue_distribution(origin, distribution: Vec<types::DistributionShare>) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            Self::_validate_revenue_distribution(&distribution)?;
+            RevenueDistribution::put(&distribution);
+            Self::deposit_event(RawEvent::RevenueDistributionSet(distribution));
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        fn set_discount_configuration(origin, tiers: Vec<types::DiscountTier>, certified_surcharge: Perbill) {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+            Self::_validate_discount_tiers(&tiers)?;
+            let discount_configuration = types::DiscountConfiguration {
+                tiers,
+                certified_surcharge,
+            };
+            DiscountConfiguration::put(&discount_configuration);
+            Self::deposit_event(RawEvent::DiscountConfigurationSet(discount_configuration));
+        }
+
+        fn on_runtime_upgrade() -> Weight {
+            Self::migrate_storage()
         }
 
         fn on_finalize(block: T::BlockNumber) {
@@ -144,26 +397,64 @@ decl_module! {
             // clean storage map for billed contracts at block
             let current_block_u64: u64 = block.saturated_into::<u64>();
             ContractsToBillAt::remove(current_block_u64);
+
+            match Self::_cancel_undeployed_contracts_at_block(block) {
+                Ok(_) => {
+                    debug::info!("undeployed contracts canceled successfully at block: {:?}", block);
+                },
+                Err(err) => {
+                    debug::info!("canceling undeployed contracts failed at block: {:?} with err {:?}", block, err);
+                }
+            }
+            ContractsToDeployBy::remove(current_block_u64);
         }
     }
 }
 
 impl<T: Config> Module<T> {
+    fn _get_twin_id(account_id: &T::AccountId) -> Result<u32, DispatchError> {
+        ensure!(
+            pallet_tfgrid::TwinIdByAccountID::<T>::contains_key(account_id),
+            Error::<T>::TwinNotExists
+        );
+        Ok(pallet_tfgrid::TwinIdByAccountID::<T>::get(account_id))
+    }
+
+    // An account can create contracts for a twin it has been delegated the contracts permission by
+    fn _ensure_authorized_to_create_contract(account_id: &T::AccountId, twin_id: u32) -> DispatchResult {
+        ensure!(
+            pallet_tfgrid::Twins::<T>::contains_key(twin_id),
+            Error::<T>::TwinNotExists
+        );
+        ensure!(
+            pallet_tfgrid::Module::<T>::is_authorized_for_twin(
+                account_id,
+                twin_id,
+                pallet_tfgrid_types::DelegatePermission::Contracts
+            ),
+            Error::<T>::TwinNotAuthorizedToCreateContract
+        );
+        Ok(())
+    }
+
     pub fn _create_node_contract(
-        account_id: T::AccountId,
+        twin_id: u32,
         node_id: u32,
         deployment_data: Vec<u8>,
         deployment_hash: Vec<u8>,
         public_ips: u32,
+        requested_ips: Vec<Vec<u8>>,
     ) -> DispatchResult {
-        ensure!(
-            pallet_tfgrid::TwinIdByAccountID::<T>::contains_key(&account_id),
-            Error::<T>::TwinNotExists
-        );
+        Self::_ensure_twin_has_no_debt(twin_id)?;
         ensure!(
             pallet_tfgrid::Nodes::contains_key(&node_id),
             Error::<T>::NodeNotExists
         );
+        // only allow deployments on nodes that are online
+        ensure!(
+            pallet_tfgrid::Module::<T>::get_node_status(node_id) == pallet_tfgrid_types::NodeStatus::Up,
+            Error::<T>::NodeNotAvailableToDeploy
+        );
 
         // if the contract with hash and node id exists and it's in any other state then
         // contractState::Deleted then we don't allow the creation of it.
@@ -179,16 +470,18 @@ impl<T: Config> Module<T> {
         let mut id = ContractID::get();
         id = id + 1;
 
-        let twin_id = pallet_tfgrid::TwinIdByAccountID::<T>::get(&account_id);
-
-        let node_contract = types::NodeContract {
+        let mut node_contract = types::NodeContract {
             node_id,
             deployment_data,
             deployment_hash: deployment_hash.clone(),
             public_ips,
             public_ips_list: Vec::new(),
+            public_ipv6_prefix_length: 0,
+            public_ipv6_list: Vec::new(),
         };
 
+        Self::_reserve_ip(id, &mut node_contract, &requested_ips)?;
+
         let contract = Self::_create_contract(
             twin_id,
             types::ContractData::NodeContract(node_contract.clone()),
@@ -200,22 +493,31 @@ impl<T: Config> Module<T> {
         node_contracts.push(id);
         ActiveNodeContracts::insert(&node_contract.node_id, &node_contracts);
 
+        // billing only starts once the node confirms the deployment,
+        // if it doesn't within the deployment timeout the contract is canceled
+        Self::_schedule_deployment_deadline(id);
+
         Self::deposit_event(RawEvent::ContractCreated(contract));
 
         Ok(())
     }
 
+    // Cancels a contract if it is not deployed within the deployment timeout
+    fn _schedule_deployment_deadline(contract_id: u64) {
+        let now = <frame_system::Module<T>>::block_number().saturated_into::<u64>();
+        let deadline = now + T::DeploymentTimeout::get();
+        let mut contracts = ContractsToDeployBy::get(deadline);
+        contracts.push(contract_id);
+        ContractsToDeployBy::insert(deadline, &contracts);
+    }
+
     fn _create_contract(
         twin_id: u32,
-        mut contract_type: types::ContractData,
+        contract_type: types::ContractData,
     ) -> Result<types::Contract, DispatchError> {
         let mut id = ContractID::get();
         id = id + 1;
 
-        if let types::ContractData::NodeContract(ref mut nc) = contract_type {
-            Self::_reserve_ip(id, nc)?;
-        };
-
         let contract = types::Contract {
             version: CONTRACT_VERSION,
             twin_id,
@@ -231,10 +533,6 @@ impl<T: Config> Module<T> {
             previous_nu_reported: 0,
         };
 
-        // Start billing frequency loop
-        // Will always be block now + frequency
-        Self::_reinsert_contract_to_bill(contract.contract_id);
-
         Contracts::insert(id, &contract);
         ContractID::put(id);
         ContractBillingInformationByID::insert(id, contract_billing_information);
@@ -243,11 +541,66 @@ impl<T: Config> Module<T> {
         Ok(contract)
     }
 
+    // The resources of a canceled contract are freed, and a contract in its grace period is
+    // not paid for, so neither can reserve more
+    fn _ensure_resources_can_change(contract: &types::Contract) -> DispatchResult {
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+        ensure!(
+            !matches!(contract.state, types::ContractState::GracePeriod(_)),
+            Error::<T>::ContractInGracePeriod
+        );
+        Ok(())
+    }
+
+    pub fn _reserve_node_contract_ipv6(
+        account_id: T::AccountId,
+        contract_id: u64,
+        prefix_length: u8,
+    ) -> DispatchResult {
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+
+        let mut contract = Contracts::get(contract_id);
+        ensure!(
+            pallet_tfgrid::Module::<T>::is_authorized_for_twin(
+                &account_id,
+                contract.twin_id,
+                pallet_tfgrid_types::DelegatePermission::Contracts
+            ),
+            Error::<T>::TwinNotAuthorizedToUpdateContract
+        );
+        Self::_ensure_resources_can_change(&contract)?;
+
+        let mut node_contract = Self::get_node_contract(&contract)?;
+        ensure!(
+            node_contract.public_ipv6_prefix_length == 0,
+            Error::<T>::NodeContractHasIPv6
+        );
+        ensure!(
+            prefix_length > 0 && prefix_length <= 128,
+            Error::<T>::InvalidIPv6PrefixLength
+        );
+
+        node_contract.public_ipv6_prefix_length = prefix_length;
+        Self::_reserve_ipv6(contract_id, &mut node_contract)?;
+
+        contract.contract_type = types::ContractData::NodeContract(node_contract);
+        Contracts::insert(contract_id, &contract);
+
+        Self::deposit_event(RawEvent::ContractUpdated(contract));
+
+        Ok(())
+    }
+
     pub fn _update_node_contract(
         account_id: T::AccountId,
         contract_id: u64,
         deployment_data: Vec<u8>,
         deployment_hash: Vec<u8>,
+        public_ips: Option<u32>,
+        requested_ips: Vec<Vec<u8>>,
     ) -> DispatchResult {
         ensure!(
             Contracts::contains_key(contract_id),
@@ -255,16 +608,34 @@ impl<T: Config> Module<T> {
         );
 
         let mut contract = Contracts::get(contract_id);
-        let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
         ensure!(
-            twin.account_id == account_id,
+            pallet_tfgrid::Module::<T>::is_authorized_for_twin(
+                &account_id,
+                contract.twin_id,
+                pallet_tfgrid_types::DelegatePermission::Contracts
+            ),
             Error::<T>::TwinNotAuthorizedToUpdateContract
         );
+        Self::_ensure_resources_can_change(&contract)?;
 
         let mut node_contract = Self::get_node_contract(&contract.clone())?;
 
+        // reserve or free ips when the number of ips changes and swap in the requested ips,
+        // this can fail so it is done before anything else is written
+        let public_ips_changed = public_ips.is_some() && public_ips != Some(node_contract.public_ips);
+        if public_ips_changed && contract.state != types::ContractState::Created {
+            // the ips used since the last bill are billed at the old count
+            Self::_accrue_unbilled_cost(&contract)?;
+        }
+        if let Some(count) = public_ips {
+            node_contract.public_ips = count;
+        }
+        if public_ips_changed || !requested_ips.is_empty() {
+            Self::_reserve_ip(contract_id, &mut node_contract, &requested_ips)?;
+        }
+
         // remove and reinsert contract id by node id and hash because that hash can have changed
-        ContractIDByNodeIDAndHash::remove(node_contract.node_id, node_contract.deployment_hash);
+        ContractIDByNodeIDAndHash::remove(node_contract.node_id, &node_contract.deployment_hash);
         ContractIDByNodeIDAndHash::insert(node_contract.node_id, &deployment_hash, contract_id);
 
         node_contract.deployment_data = deployment_data;
@@ -287,18 +658,71 @@ impl<T: Config> Module<T> {
             Error::<T>::ContractNotExists
         );
 
-        let mut contract = Contracts::get(contract_id);
-        let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
+        let contract = Contracts::get(contract_id);
         ensure!(
-            twin.account_id == account_id,
+            pallet_tfgrid::Module::<T>::is_authorized_for_twin(
+                &account_id,
+                contract.twin_id,
+                pallet_tfgrid_types::DelegatePermission::Contracts
+            ),
             Error::<T>::TwinNotAuthorizedToCancelContract
         );
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+
+        Self::_settle_and_remove_contract(contract, cause)
+    }
+
+    // Removes a contract, bills a node contract for the usage since its last bill
+    // and takes the contract out of the billing schedule
+    fn _settle_and_remove_contract(contract: types::Contract, cause: types::Cause) -> DispatchResult {
+        let contract_id = contract.contract_id;
+        // node and domain contracts are only billed once they are deployed
+        let is_billed = match contract.contract_type {
+            types::ContractData::NodeContract(_) => contract.state != types::ContractState::Created,
+            types::ContractData::NameContract(_) => true,
+            types::ContractData::DomainContract(_) => contract.state != types::ContractState::Created,
+        };
+
+        // the contract runs until now, later bills do not add to this
+        if is_billed {
+            if let Err(err) = Self::_accrue_unbilled_cost(&contract) {
+                debug::info!("error while accruing the cost of contract with id {:?}: {:?}", contract_id, err);
+            }
+        }
+
+        Self::_remove_contract(contract, cause)?;
+
+        if is_billed {
+            // the contract is deleted, so what the twin cannot pay is kept as debt
+            let mut contract = Contracts::get(contract_id);
+            let result = match contract.contract_type {
+                types::ContractData::NodeContract(_) => Self::_bill_node_contract(&mut contract),
+                types::ContractData::NameContract(_) => Self::_bill_name_contract(&mut contract),
+                types::ContractData::DomainContract(_) => Self::_bill_domain_contract(&mut contract),
+            };
+            if let Err(err) = result {
+                // keep the contract in the billing schedule, its unbilled amount is billed at the next cycle
+                debug::info!("error while settling contract with id {:?}: {:?}", contract_id, err);
+                return Ok(());
+            }
+        }
+
+        Self::_remove_contract_from_billing(contract_id);
+
+        Ok(())
+    }
 
+    // Frees the resources held by a contract and marks it as deleted
+    fn _remove_contract(mut contract: types::Contract, cause: types::Cause) -> DispatchResult {
+        let contract_id = contract.contract_id;
         match contract.contract_type.clone() {
             types::ContractData::NodeContract(mut node_contract) => {
                 if node_contract.public_ips > 0 {
                     Self::_free_ip(contract_id, &mut node_contract)?
                 }
+                if node_contract.public_ipv6_prefix_length > 0 {
+                    Self::_free_ipv6(contract_id, &mut node_contract)?
+                }
 
                 // remove the contract by hash from storage
                 ContractIDByNodeIDAndHash::remove(
@@ -313,8 +737,13 @@ impl<T: Config> Module<T> {
             }
             types::ContractData::NameContract(name_contract) => {
                 ContractIDByNameRegistration::remove(name_contract.name);
+                NameContractTransferOffers::remove(contract_id);
                 Self::deposit_event(RawEvent::NameContractCanceled(contract_id));
             }
+            types::ContractData::DomainContract(domain_contract) => {
+                ContractIDByDomain::remove(domain_contract.domain);
+                Self::deposit_event(RawEvent::DomainContractCanceled(contract_id));
+            }
         };
 
         Self::_update_contract_state(&mut contract, &types::ContractState::Deleted(cause))?;
@@ -322,6 +751,115 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
+    // Checks whether the account is the twin of the node
+    fn _is_node_twin(account_id: &T::AccountId, node_id: u32) -> bool {
+        let node = pallet_tfgrid::Nodes::get(node_id);
+        pallet_tfgrid::TwinIdByAccountID::<T>::contains_key(account_id)
+            && pallet_tfgrid::TwinIdByAccountID::<T>::get(account_id) == node.twin_id
+    }
+
+    // Called by a node that can no longer host the workloads of a contract.
+    // What is owed up to now is billed before the contract is removed.
+    pub fn _cancel_contract_by_node(account_id: T::AccountId, contract_id: u64, cause: types::Cause) -> DispatchResult {
+        ensure!(
+            matches!(
+                cause,
+                types::Cause::NodeFailure | types::Cause::FarmDecommissioned | types::Cause::PolicyViolation
+            ),
+            Error::<T>::InvalidCancelCause
+        );
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+        let contract = Contracts::get(contract_id);
+        let node_contract = Self::get_node_contract(&contract)?;
+        ensure!(
+            Self::_is_node_twin(&account_id, node_contract.node_id),
+            Error::<T>::NodeNotAuthorizedToCancelContract
+        );
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+
+        Self::_settle_and_remove_contract(contract.clone(), cause.clone())?;
+
+        Self::deposit_event(RawEvent::NodeContractCanceledByNode(
+            contract_id,
+            node_contract.node_id,
+            contract.twin_id,
+            cause,
+        ));
+
+        Ok(())
+    }
+
+    // Returns a node contract that is still waiting for its node to confirm the deployment
+    fn _get_contract_to_deploy(account_id: &T::AccountId, contract_id: u64) -> Result<types::Contract, DispatchError> {
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+        let contract = Contracts::get(contract_id);
+        let node_contract = Self::get_node_contract(&contract)?;
+        ensure!(
+            Self::_is_node_twin(account_id, node_contract.node_id),
+            Error::<T>::NodeNotAuthorizedToDeployContract
+        );
+        ensure!(
+            contract.state == types::ContractState::Created,
+            Error::<T>::ContractNotAwaitingDeployment
+        );
+        Ok(contract)
+    }
+
+    // Called by the node once the workloads of a contract are deployed, billing starts from here on
+    pub fn _deploy_contract(account_id: T::AccountId, contract_id: u64) -> DispatchResult {
+        let mut contract = Self::_get_contract_to_deploy(&account_id, contract_id)?;
+
+        Self::_update_contract_state(&mut contract, &types::ContractState::Deployed)?;
+
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        let mut contract_billing_information = ContractBillingInformationByID::get(contract_id);
+        contract_billing_information.last_updated = now;
+        ContractBillingInformationByID::insert(contract_id, contract_billing_information);
+        ContractLastBilledAt::insert(contract_id, now);
+
+        // Start billing frequency loop
+        // Will always be block now + frequency
+        Self::_reinsert_contract_to_bill(contract_id);
+
+        Self::deposit_event(RawEvent::ContractDeployed(contract_id, account_id));
+
+        Ok(())
+    }
+
+    // Called by the node if it cannot deploy the workloads of a contract
+    pub fn _reject_contract(account_id: T::AccountId, contract_id: u64) -> DispatchResult {
+        let contract = Self::_get_contract_to_deploy(&account_id, contract_id)?;
+        Self::_remove_contract(contract, types::Cause::RejectedByNode)
+    }
+
+    // Cancels the node contracts that were not confirmed by their node before the deployment timeout
+    pub fn _cancel_undeployed_contracts_at_block(block: T::BlockNumber) -> DispatchResult {
+        let current_block_u64: u64 = block.saturated_into::<u64>();
+        let contracts = ContractsToDeployBy::get(current_block_u64);
+        for contract_id in contracts {
+            let contract = Contracts::get(contract_id);
+            if contract.state != types::ContractState::Created {
+                continue;
+            }
+
+            match Self::_remove_contract(contract, types::Cause::DeploymentTimeout) {
+                Ok(_) => {
+                    debug::info!("canceled undeployed contract with id {:?} at block {:?}", contract_id, block);
+                }
+                Err(err) => {
+                    debug::info!("error while canceling undeployed contract with id {:?}: {:?}", contract_id, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn _compute_reports(
         source: T::AccountId,
         reports: Vec<types::Consumption>,
@@ -370,6 +908,12 @@ impl<T: Config> Module<T> {
         }
 
         for report in reports {
+            // contracts are only billed for usage once their node confirmed the deployment
+            if Contracts::contains_key(report.contract_id)
+                && Contracts::get(report.contract_id).state == types::ContractState::Created
+            {
+                continue;
+            }
             Self::_calculate_report_cost(&report, &pricing_policy)?;
             Self::deposit_event(RawEvent::ConsumptionReportReceived(report));
         }
@@ -455,9 +999,10 @@ impl<T: Config> Module<T> {
             let mut contract = Contracts::get(contract_id);
             let contract_billing_info = ContractBillingInformationByID::get(contract_id);
 
-            // if the contract is in any other state then created and it has no unbilled amounts left, skip it
+            // if the contract is deleted and it has no unbilled amounts left, skip it
             // this contract will be removed from the billing cycle when this function returns
-            if contract.state != types::ContractState::Created && contract_billing_info.amount_unbilled == 0 {
+            if contract.is_state_delete() && contract_billing_info.amount_unbilled == 0 {
+                ContractNextBillingBlock::remove(contract_id);
                 continue;
             }
 
@@ -467,6 +1012,7 @@ impl<T: Config> Module<T> {
             let result = match contract.contract_type {
                 types::ContractData::NodeContract(_) => Self::_bill_node_contract(&mut contract),
                 types::ContractData::NameContract(_) => Self::_bill_name_contract(&mut contract),
+                types::ContractData::DomainContract(_) => Self::_bill_domain_contract(&mut contract),
             };
     
             match result {
@@ -499,12 +1045,7 @@ impl<T: Config> Module<T> {
         let pricing_policy = pallet_tfgrid::PricingPolicies::<T>::get(farm.pricing_policy_id);
 
         let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
-        let mut seconds_elapsed = T::BillingFrequency::get() * 6;
-
-        if ContractLastBilledAt::contains_key(contract.contract_id) {
-            let contract_last_billed_at = ContractLastBilledAt::get(contract.contract_id);
-            seconds_elapsed = now - contract_last_billed_at;
-        }
+        let seconds_elapsed = Self::_seconds_since_last_bill(contract, now);
 
         // bill user for 1 hour ip usage (60 blocks * 60 seconds)
         let total_ip_cost =  U64F64::from_num(node_contract.public_ips) 
@@ -519,16 +1060,12 @@ impl<T: Config> Module<T> {
             return Ok(());
         }
 
-        let tft_price_musd = U64F64::from_num(pallet_tft_price::AverageTftPrice::get()) * 1000;
-        if tft_price_musd <= U64F64::from_num(0) {
-            debug::info!("TFT price is zero");
-            return Err(DispatchError::from(Error::<T>::TFTPriceValueError));
-        }
-
-        let total_cost_musd = U64F64::from_num(total_cost)/10000;
+        let total_cost_tft_64 = Self::_cost_to_tft(total_cost)?;
 
-        let total_cost_tft = (total_cost_musd / tft_price_musd) * U64F64::from_num(1e7);
-        let total_cost_tft_64 = U64F64::to_num(total_cost_tft);
+        // outstanding debt of the twin is paid off first
+        if let Err(err) = Self::_settle_debt(contract.twin_id) {
+            debug::info!("error while settling debt of twin {:?}: {:?}", contract.twin_id, err);
+        }
 
         let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
         let balance: BalanceOf<T> = <T as Config>::Currency::free_balance(&twin.account_id);
@@ -540,15 +1077,47 @@ impl<T: Config> Module<T> {
         let amount_due_as_u128: u128 = amount_due.saturated_into::<u128>();
         // Get current TFT price
 
-        // if the total amount due exceeds the twin's balance, decomission contract
-        // but first drain the account with the amount equal to the balance of that twin
+        // if the total amount due exceeds the twin's balance, the contract enters a grace period
+        // in which the workload is kept and the cost accrues until the twin is funded again.
+        // When the grace period ended, decomission contract
+        // but first drain the account with the amount equal to the balance of that twin,
+        // the rest of the amount due is kept as debt of the twin
         let mut amount_due: BalanceOf<T> = BalanceOf::<T>::saturated_from(amount_due_as_u128);
+        let current_block = <frame_system::Module<T>>::block_number().saturated_into::<u64>();
 
         let mut decomission = false;
+        let mut debt: BalanceOf<T> = Zero::zero();
         if amount_due >= balance {
-            debug::info!("decomissioning contract because balance on twin account is lower than amount due");
-            amount_due = balance;
-            decomission = true;
+            match contract.state {
+                types::ContractState::GracePeriod(start_block) if current_block >= start_block + T::GracePeriod::get() => {
+                    debug::info!("decomissioning contract because the grace period ended and balance on twin account is lower than amount due");
+                    debt = amount_due - balance;
+                    amount_due = balance;
+                    decomission = true;
+                }
+                // a deleted contract is not kept in a grace period, it is billed for the last time
+                types::ContractState::Deleted(_) => {
+                    debt = amount_due - balance;
+                    amount_due = balance;
+                }
+                _ => {
+                    contract_billing_info.amount_unbilled = total_cost;
+                    ContractBillingInformationByID::insert(contract.contract_id, &contract_billing_info);
+                    ContractLastBilledAt::insert(contract.contract_id, now);
+
+                    if !matches!(contract.state, types::ContractState::GracePeriod(_)) {
+                        debug::info!("contract enters grace period because balance on twin account is lower than amount due");
+                        Self::_update_contract_state(contract, &types::ContractState::GracePeriod(current_block))?;
+                        Self::deposit_event(RawEvent::ContractGracePeriodStarted(
+                            contract.contract_id,
+                            node_contract.node_id,
+                            contract.twin_id,
+                            current_block,
+                        ));
+                    }
+                    return Ok(());
+                }
+            }
         }
 
         // Distribute cultivation rewards
@@ -569,67 +1138,306 @@ impl<T: Config> Module<T> {
         contract_billing_info.amount_unbilled = 0;
         ContractBillingInformationByID::insert(contract.contract_id, &contract_billing_info);
         ContractLastBilledAt::insert(contract.contract_id, now);
-        
+
+        if !debt.is_zero() {
+            Self::_add_debt(contract, debt);
+        }
+        
         // If total balance exceeds the twin's balance, we can decomission contract
         if decomission {
             let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
             return Self::_cancel_contract(twin.account_id, contract.contract_id, types::Cause::OutOfFunds);
         }
 
+        // the twin was funded again, restore the contract
+        if matches!(contract.state, types::ContractState::GracePeriod(_)) {
+            Self::_update_contract_state(contract, &types::ContractState::Deployed)?;
+            Self::deposit_event(RawEvent::ContractGracePeriodEnded(
+                contract.contract_id,
+                node_contract.node_id,
+                contract.twin_id,
+            ));
+        }
+
         Ok(())
     }
 
     fn _bill_name_contract(contract: &mut types::Contract) -> DispatchResult {
-        // TODO: CHANGE THIS!!!!
-        // we cannot now which pricing policy to fetch because a name contract is not linked to a node/farm
-        let pricing_policy = pallet_tfgrid::PricingPolicies::<T>::get(1);
-        let total_name_cost = U64F64::from_num(pricing_policy.unique_name.value);
+        let name_contract = Self::get_name_contract(contract)?;
+        ensure!(
+            pallet_tfgrid::PricingPolicies::<T>::contains_key(name_contract.pricing_policy_id),
+            Error::<T>::PricingPolicyNotExists
+        );
+        let pricing_policy = pallet_tfgrid::PricingPolicies::<T>::get(name_contract.pricing_policy_id);
+        let price = pricing_policy.unique_name.value;
+
+        // a name contract is not linked to a farm, so it is billed as a diy deployment
+        Self::_bill_contract_at_price(
+            contract,
+            &pricing_policy,
+            price,
+            pallet_tfgrid_types::CertificationType::Diy,
+        )
+    }
+
+    fn _bill_domain_contract(contract: &mut types::Contract) -> DispatchResult {
+        let domain_contract = Self::get_domain_contract(contract)?;
+
+        // the domain can no longer be served once its gateway is removed, canceling the contract
+        // bills what it ran so far. Without the gateway's farm it is billed as a diy deployment
+        // with the default pricing policy of name contracts
+        if !pallet_tfgrid::Gateways::<T>::contains_key(domain_contract.gateway_id) {
+            if !contract.is_state_delete() {
+                let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
+                return Self::_cancel_contract(twin.account_id, contract.contract_id, types::Cause::GatewayRemoved);
+            }
+            let pricing_policy = Self::_get_pricing_policy(contract)?;
+            let price = pricing_policy.domain_name.value;
+            return Self::_bill_contract_at_price(
+                contract,
+                &pricing_policy,
+                price,
+                pallet_tfgrid_types::CertificationType::Diy,
+            );
+        }
+
+        let gateway = pallet_tfgrid::Gateways::<T>::get(domain_contract.gateway_id);
+        ensure!(
+            pallet_tfgrid::Farms::contains_key(gateway.farm_id),
+            Error::<T>::FarmNotExists
+        );
+        let farm = pallet_tfgrid::Farms::get(gateway.farm_id);
+        let pricing_policy = Self::_get_pricing_policy(contract)?;
+        let price = pricing_policy.domain_name.value;
+
+        Self::_bill_contract_at_price(contract, &pricing_policy, price, farm.certification_type)
+    }
+
+    // Bills a contract with a fixed price per hour for the seconds elapsed since its last bill
+    fn _bill_contract_at_price(
+        contract: &mut types::Contract,
+        pricing_policy: &pallet_tfgrid_types::PricingPolicy<T::AccountId>,
+        price: u32,
+        certification_type: pallet_tfgrid_types::CertificationType,
+    ) -> DispatchResult {
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        let seconds_elapsed = Self::_seconds_since_last_bill(contract, now);
+
+        // the price is expressed in 1 hours or 3600 seconds
+        let total_price_cost = (U64F64::from_num(price) / 3600)
+            * U64F64::from_num(seconds_elapsed);
+        let mut contract_billing_info = ContractBillingInformationByID::get(contract.contract_id);
+        let total_cost = total_price_cost.to_num::<u64>() + contract_billing_info.amount_unbilled;
+
+        if total_cost == 0 {
+            return Ok(());
+        }
+
+        let total_cost_tft_64 = Self::_cost_to_tft(total_cost)?;
+
+        // outstanding debt of the twin is paid off first
+        if let Err(err) = Self::_settle_debt(contract.twin_id) {
+            debug::info!("error while settling debt of twin {:?}: {:?}", contract.twin_id, err);
+        }
+
         // get the contract's twin free balance
         let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
         let balance: BalanceOf<T> = <T as Config>::Currency::free_balance(&twin.account_id);
         debug::info!("free balance: {:?}", balance);
 
-        //Get TFT price
-        let tft_price = U64F64::from_num(pallet_tft_price::AverageTftPrice::get());
-        if tft_price <= U64F64::from_num(0) {
-            debug::info!("TFT price is zero");
-            return Err(DispatchError::from(Error::<T>::TFTPriceValueError));
-        }
-        //Convert amount due to TFT
-        let amount_due_tft = total_name_cost / tft_price;
-        // // Calculate the amount due and discount received based on the total_cost amount due
-        // let (mut amount_due, discount_received) = Self::_calculate_discount(total_name_cost, balance, farm.certification_type);
-        // // Convert amount due to u128
-        let mut amount_due_tft_as_u128: u128 = U64F64::to_num(amount_due_tft);
-        let balance_as_u128: u128 = balance.saturated_into::<u128>();
+        // Calculate the amount due and discount received based on the total_cost amount due
+        let (mut amount_due, discount_received) =
+            Self::_calculate_discount(total_cost_tft_64, balance, certification_type);
 
         // if the total amount due exceeds the twin's balance, decomission contract
-        // but first drain the account with the amount equal to the balance of that twin
+        // but first drain the account with the amount equal to the balance of that twin,
+        // the rest of the amount due is kept as debt of the twin
         let mut decomission = false;
-        if amount_due_tft_as_u128 >= balance_as_u128 {
-            amount_due_tft_as_u128 = balance_as_u128;
-            decomission = true;
+        let mut debt: BalanceOf<T> = Zero::zero();
+        if amount_due >= balance {
+            debt = amount_due - balance;
+            amount_due = balance;
+            decomission = !contract.is_state_delete();
         }
 
+        // Distribute cultivation rewards
+        match Self::_distribute_cultivation_rewards(contract, pricing_policy, amount_due) {
+            Ok(_) => (),
+            Err(err) => debug::info!("error while distributing cultivation rewards {:?}", err)
+        };
+
         let contract_bill = types::ContractBill {
             contract_id: contract.contract_id,
-            timestamp: <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000,
-            // TODO: maybe change this
-            discount_level: types::DiscountLevel::None,
-            amount_billed: amount_due_tft_as_u128,
+            timestamp: now,
+            discount_level: discount_received,
+            amount_billed: amount_due.saturated_into::<u128>(),
         };
         Self::deposit_event(RawEvent::ContractBilled(contract_bill));
 
+        contract_billing_info.amount_unbilled = 0;
+        ContractBillingInformationByID::insert(contract.contract_id, &contract_billing_info);
+        ContractLastBilledAt::insert(contract.contract_id, now);
+
+        if !debt.is_zero() {
+            Self::_add_debt(contract, debt);
+        }
+
         // If total balance exceeds the twin's balance, we can decomission contract
         if decomission {
-            let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
             Self::_cancel_contract(twin.account_id, contract.contract_id, types::Cause::OutOfFunds)?;
         }
 
         Ok(())
     }
 
-    // Following: https://library.threefold.me/info/threefold#/tfgrid/farming/threefold__proof_of_utilization
+    // Seconds a contract ran since its last bill, a canceled contract does not run anymore
+    fn _seconds_since_last_bill(contract: &types::Contract, now: u64) -> u64 {
+        if contract.is_state_delete() {
+            return 0;
+        }
+        if ContractLastBilledAt::contains_key(contract.contract_id) {
+            now.saturating_sub(ContractLastBilledAt::get(contract.contract_id))
+        } else {
+            T::BillingFrequency::get() * 6
+        }
+    }
+
+    // Moves the cost of the time a contract ran since its last bill into its unbilled amount,
+    // so that time is billed at the current price and public ips even if those change
+    fn _accrue_unbilled_cost(contract: &types::Contract) -> DispatchResult {
+        let pricing_policy = Self::_get_pricing_policy(contract)?;
+        let (units, price) = match &contract.contract_type {
+            types::ContractData::NodeContract(node_contract) => (node_contract.public_ips, pricing_policy.ipu.value),
+            types::ContractData::NameContract(_) => (1, pricing_policy.unique_name.value),
+            types::ContractData::DomainContract(_) => (1, pricing_policy.domain_name.value),
+        };
+
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        let cost = U64F64::from_num(units)
+            * (U64F64::from_num(price) / 3600)
+            * U64F64::from_num(Self::_seconds_since_last_bill(contract, now));
+
+        ContractBillingInformationByID::mutate(contract.contract_id, |contract_billing_info| {
+            contract_billing_info.amount_unbilled += cost.to_num::<u64>()
+        });
+        ContractLastBilledAt::insert(contract.contract_id, now);
+
+        Ok(())
+    }
+
+    // Converts a cost in units of 1/10000 mUSD to an amount of TFT at the current TFT price
+    fn _cost_to_tft(total_cost: u64) -> Result<u64, DispatchError> {
+        let tft_price_musd = U64F64::from_num(pallet_tft_price::AverageTftPrice::get()) * 1000;
+        if tft_price_musd <= U64F64::from_num(0) {
+            debug::info!("TFT price is zero");
+            return Err(DispatchError::from(Error::<T>::TFTPriceValueError));
+        }
+
+        let total_cost_musd = U64F64::from_num(total_cost)/10000;
+
+        let total_cost_tft = (total_cost_musd / tft_price_musd) * U64F64::from_num(1e7);
+        Ok(U64F64::to_num(total_cost_tft))
+    }
+
+    // Returns the pricing policy a contract is billed with
+    fn _get_pricing_policy(
+        contract: &types::Contract,
+    ) -> Result<pallet_tfgrid_types::PricingPolicy<T::AccountId>, DispatchError> {
+        let pricing_policy_id = match &contract.contract_type {
+            types::ContractData::NodeContract(node_contract) => {
+                let node = pallet_tfgrid::Nodes::get(node_contract.node_id);
+                pallet_tfgrid::Farms::get(node.farm_id).pricing_policy_id
+            }
+            types::ContractData::NameContract(name_contract) => name_contract.pricing_policy_id,
+            types::ContractData::DomainContract(domain_contract) => {
+                // a removed gateway leaves no farm to take the pricing policy from
+                if !pallet_tfgrid::Gateways::<T>::contains_key(domain_contract.gateway_id) {
+                    return Self::_get_default_pricing_policy();
+                }
+                let gateway = pallet_tfgrid::Gateways::<T>::get(domain_contract.gateway_id);
+                pallet_tfgrid::Farms::get(gateway.farm_id).pricing_policy_id
+            }
+        };
+        ensure!(
+            pallet_tfgrid::PricingPolicies::<T>::contains_key(pricing_policy_id),
+            Error::<T>::PricingPolicyNotExists
+        );
+        Ok(pallet_tfgrid::PricingPolicies::<T>::get(pricing_policy_id))
+    }
+
+    // The pricing policy new name contracts get
+    fn _get_default_pricing_policy() -> Result<pallet_tfgrid_types::PricingPolicy<T::AccountId>, DispatchError> {
+        let pricing_policy_id = NameContractPricingPolicyID::get();
+        ensure!(
+            pallet_tfgrid::PricingPolicies::<T>::contains_key(pricing_policy_id),
+            Error::<T>::PricingPolicyNotExists
+        );
+        Ok(pallet_tfgrid::PricingPolicies::<T>::get(pricing_policy_id))
+    }
+
+    // Adds the part of a bill the twin could not pay to the debt of the contract and the twin
+    fn _add_debt(contract: &types::Contract, amount: BalanceOf<T>) {
+        ContractDebt::<T>::mutate(contract.twin_id, contract.contract_id, |debt| {
+            *debt = debt.saturating_add(amount)
+        });
+        TwinDebt::<T>::mutate(contract.twin_id, |debt| *debt = debt.saturating_add(amount));
+        Self::deposit_event(RawEvent::DebtIncurred(contract.contract_id, contract.twin_id, amount));
+    }
+
+    // Pays off the debt of a twin as far as its balance allows
+    pub fn _settle_debt(twin_id: u32) -> DispatchResult {
+        if TwinDebt::<T>::get(twin_id).is_zero() {
+            return Ok(());
+        }
+
+        let twin = pallet_tfgrid::Twins::<T>::get(twin_id);
+        let debts: Vec<(u64, BalanceOf<T>)> = ContractDebt::<T>::iter_prefix(twin_id).collect();
+        for (contract_id, debt) in debts {
+            // keep the twin account alive
+            let available = <T as Config>::Currency::free_balance(&twin.account_id)
+                .saturating_sub(<T as Config>::Currency::minimum_balance());
+            if available.is_zero() {
+                break;
+            }
+            let amount = if debt < available { debt } else { available };
+
+            // the node, farm or pricing policy of the contract can be gone by now,
+            // the debt is then paid to the default pricing policy
+            let contract = Contracts::get(contract_id);
+            let pricing_policy = match Self::_get_pricing_policy(&contract) {
+                Ok(pricing_policy) => pricing_policy,
+                Err(_) => Self::_get_default_pricing_policy()?,
+            };
+
+            Self::_distribute_cultivation_rewards(&contract, &pricing_policy, amount)?;
+
+            if amount == debt {
+                ContractDebt::<T>::remove(twin_id, contract_id);
+            } else {
+                ContractDebt::<T>::insert(twin_id, contract_id, debt - amount);
+            }
+            let twin_debt = TwinDebt::<T>::get(twin_id).saturating_sub(amount);
+            if twin_debt.is_zero() {
+                TwinDebt::<T>::remove(twin_id);
+            } else {
+                TwinDebt::<T>::insert(twin_id, twin_debt);
+            }
+            Self::deposit_event(RawEvent::DebtSettled(contract_id, twin_id, amount));
+        }
+
+        Ok(())
+    }
+
+    // A twin with outstanding debt cannot create new contracts, unless its debt can be settled now
+    fn _ensure_twin_has_no_debt(twin_id: u32) -> DispatchResult {
+        Self::_settle_debt(twin_id)?;
+        ensure!(
+            TwinDebt::<T>::get(twin_id).is_zero(),
+            Error::<T>::TwinHasDebt
+        );
+        Ok(())
+    }
+
     fn _distribute_cultivation_rewards(
         contract: &types::Contract,
         pricing_policy: &pallet_tfgrid_types::PricingPolicy<T::AccountId>,
@@ -638,56 +1446,40 @@ impl<T: Config> Module<T> {
         // fetch source twin
         let twin = pallet_tfgrid::Twins::<T>::get(contract.twin_id);
 
-        // Send 10% to the foundation
-        let foundation_share = Perbill::from_percent(10) * amount;
-        debug::info!(
-            "Transfering: {:?} from contract twin {:?} to foundation account {:?}",
-            &foundation_share,
-            &twin.account_id,
-            &pricing_policy.foundation_account
-        );
-        <T as Config>::Currency::transfer(
-            &twin.account_id,
-            &pricing_policy.foundation_account,
-            foundation_share,
-            KeepAlive,
-        )
-        .map_err(|_| DispatchError::Other("Can't make foundation share transfer"))?;
-
-        // TODO: send 5% to the staking pool account
-        let staking_pool_share = Perbill::from_percent(5) * amount;
-        let staking_pool_account = T::StakingPoolAccount::get();
-        <T as Config>::Currency::transfer(
-            &twin.account_id,
-            &staking_pool_account,
-            staking_pool_share,
-            KeepAlive,
-        )
-        .map_err(|_| DispatchError::Other("Can't make staking pool share transfer"))?;
-
-        // Send 50% to the sales channel
-        let sales_share = Perbill::from_percent(50) * amount;
-        debug::info!(
-            "Transfering: {:?} from contract twin {:?} to foundation account {:?}",
-            &sales_share,
-            &twin.account_id,
-            &pricing_policy.certified_sales_account
-        );
-        <T as Config>::Currency::transfer(
-            &twin.account_id,
-            &pricing_policy.certified_sales_account,
-            sales_share,
-            KeepAlive,
-        )
-        .map_err(|_| DispatchError::Other("Can't make sales share transfer"))?;
+        let mut amount_transferred = BalanceOf::<T>::zero();
+        for distribution_share in RevenueDistribution::get() {
+            let destination_account = match distribution_share.destination {
+                types::DistributionDestination::Foundation => pricing_policy.foundation_account.clone(),
+                types::DistributionDestination::StakingPool => T::StakingPoolAccount::get(),
+                types::DistributionDestination::SalesChannel => pricing_policy.certified_sales_account.clone(),
+                types::DistributionDestination::Burn => continue,
+            };
+            // round down, so the shares never add up to more than the amount
+            let share = distribution_share.share.mul_floor(amount);
+            debug::info!(
+                "Transfering: {:?} from contract twin {:?} to {:?} account {:?}",
+                &share,
+                &twin.account_id,
+                &distribution_share.destination,
+                &destination_account
+            );
+            <T as Config>::Currency::transfer(
+                &twin.account_id,
+                &destination_account,
+                share,
+                KeepAlive,
+            )
+            .map_err(|_| DispatchError::Other("Can't make distribution share transfer"))?;
+            amount_transferred += share;
+        }
 
-        // Burn 35%, to not have any imbalance in the system, subtract all previously send amounts with the initial
-        let mut amount_to_burn = amount - foundation_share - staking_pool_share - sales_share;
+        // Burn the rest, to not have any imbalance in the system, subtract all previously send amounts with the initial
+        let mut amount_to_burn = amount.saturating_sub(amount_transferred);
 
         let existential_deposit_requirement = <T as Config>::Currency::minimum_balance();
         let free_balance = <T as Config>::Currency::free_balance(&twin.account_id);
-        if amount_to_burn > free_balance - existential_deposit_requirement {
-            amount_to_burn = <T as Config>::Currency::free_balance(&twin.account_id) - existential_deposit_requirement;
+        if amount_to_burn > free_balance.saturating_sub(existential_deposit_requirement) {
+            amount_to_burn = free_balance.saturating_sub(existential_deposit_requirement);
         }
 
         <T as Config>::Currency::slash(&twin.account_id, amount_to_burn);
@@ -695,6 +1487,43 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
+    // The shares of a revenue distribution must add up to 100% and name every destination at most once
+    fn _validate_revenue_distribution(distribution: &[types::DistributionShare]) -> DispatchResult {
+        let mut total: u64 = 0;
+        for (i, distribution_share) in distribution.iter().enumerate() {
+            ensure!(
+                !distribution[..i].iter().any(|s| s.destination == distribution_share.destination),
+                Error::<T>::RevenueDistributionDuplicateDestination
+            );
+            total += u64::from(distribution_share.share.deconstruct());
+        }
+        ensure!(
+            total == u64::from(Perbill::one().deconstruct()),
+            Error::<T>::RevenueDistributionNotComplete
+        );
+        Ok(())
+    }
+
+    fn _perbill_to_fixed(perbill: Perbill) -> U64F64 {
+        U64F64::from_num(perbill.deconstruct()) / U64F64::from_num(Perbill::one().deconstruct())
+    }
+
+    // Discount tiers must be sorted by months ascending and give every discount level at most once
+    fn _validate_discount_tiers(tiers: &[types::DiscountTier]) -> DispatchResult {
+        for (i, tier) in tiers.iter().enumerate() {
+            ensure!(
+                tier.level != types::DiscountLevel::None
+                    && !tiers[..i].iter().any(|t| t.level == tier.level),
+                Error::<T>::DiscountTierLevelNotValid
+            );
+            ensure!(
+                i == 0 || tiers[i - 1].months < tier.months,
+                Error::<T>::DiscountTiersNotAscending
+            );
+        }
+        Ok(())
+    }
+
     // Calculates the discount that will be applied to the billing of the contract
     // Returns an amount due as balance object and a static string indicating which kind of discount it received
     // (default, bronze, silver, gold or none)
@@ -713,22 +1542,26 @@ impl<T: Config> Module<T> {
         let discount_level =
             U64F64::from_num(balance_as_u128) / U64F64::from_num(amount_due_monthly);
 
-        // predefined discount levels
-        // https://wiki.threefold.io/#/threefold__grid_pricing
-        let discount_received = match discount_level.floor().to_num::<u64>() {
-            d if d >= 3 && d < 6 => types::DiscountLevel::Default,
-            d if d >= 6 && d < 12 => types::DiscountLevel::Bronze,
-            d if d >= 12 && d < 36 => types::DiscountLevel::Silver,
-            d if d >= 36 => types::DiscountLevel::Gold,
-            _ => types::DiscountLevel::None,
+        // the tiers are sorted, the last one the balance covers applies
+        let months = discount_level.floor().to_num::<u64>();
+        let discount_configuration = DiscountConfiguration::get();
+        let (discount_received, multiplier) = match discount_configuration
+            .tiers
+            .into_iter()
+            .rev()
+            .find(|tier| months >= tier.months)
+        {
+            Some(tier) => (tier.level, tier.multiplier),
+            None => (types::DiscountLevel::None, Perbill::one()),
         };
 
         // calculate the new amount due given the discount
-        let mut amount_due = U64F64::from_num(amount_due) * discount_received.price_multiplier();
+        let mut amount_due = U64F64::from_num(amount_due) * Self::_perbill_to_fixed(multiplier);
 
-        // Certified capacity costs 25% more
+        // Certified capacity costs more
         if certification_type == pallet_tfgrid_types::CertificationType::Certified {
-            amount_due = amount_due * U64F64::from_num(1.25);
+            amount_due = amount_due
+                * (U64F64::from_num(1) + Self::_perbill_to_fixed(discount_configuration.certified_surcharge));
         }
 
         // convert to balance object
@@ -746,6 +1579,7 @@ impl<T: Config> Module<T> {
         let mut contracts = ContractsToBillAt::get(future_block);
         contracts.push(contract_id);
         ContractsToBillAt::insert(future_block, &contracts);
+        ContractNextBillingBlock::insert(contract_id, future_block);
         debug::info!(
             "Insert contracts: {:?}, to be billed at block {:?}",
             contracts,
@@ -753,6 +1587,17 @@ impl<T: Config> Module<T> {
         );
     }
 
+    // Takes a contract out of the billing schedule
+    pub fn _remove_contract_from_billing(contract_id: u64) {
+        if !ContractNextBillingBlock::contains_key(contract_id) {
+            return;
+        }
+        let block = ContractNextBillingBlock::take(contract_id);
+        let mut contracts = ContractsToBillAt::get(block);
+        contracts.retain(|id| *id != contract_id);
+        ContractsToBillAt::insert(block, &contracts);
+    }
+
     // Helper function that updates the contract state and manages storage accordingly
     pub fn _update_contract_state(
         contract: &mut types::Contract,
@@ -762,9 +1607,9 @@ impl<T: Config> Module<T> {
         contract.state = state.clone();
         Contracts::insert(&contract.contract_id, contract.clone());
 
-        // if the contract is a name contract, nothing to do left here
+        // if the contract is a name or domain contract, nothing to do left here
         match contract.contract_type {
-            types::ContractData::NameContract(_) => return Ok(()),
+            types::ContractData::NameContract(_) | types::ContractData::DomainContract(_) => return Ok(()),
             _ => (),
         };
 
@@ -786,7 +1631,7 @@ impl<T: Config> Module<T> {
             }
             None => {
                 // if the contract is not present add it to the active contracts map
-                if state == &types::ContractState::Created {
+                if !contract.is_state_delete() {
                     contracts.push(contract.contract_id);
                 }
             }
@@ -797,17 +1642,25 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
+    // Makes sure the contract holds `public_ips` ips of the farm, including the requested ones.
+    // Ips the contract already holds are kept, unless they have to make room for requested ones.
     pub fn _reserve_ip(
         contract_id: u64,
         node_contract: &mut types::NodeContract,
+        requested_ips: &[Vec<u8>],
     ) -> DispatchResult {
-        if node_contract.public_ips == 0 {
+        let public_ips = node_contract.public_ips as usize;
+        ensure!(
+            requested_ips.len() <= public_ips,
+            Error::<T>::TooManyRequestedIPs
+        );
+        if public_ips == 0 && node_contract.public_ips_list.is_empty() {
             return Ok(());
         }
         let node = pallet_tfgrid::Nodes::get(node_contract.node_id);
 
         ensure!(
-            pallet_tfgrid::Farms::contains_key(&node.farm_id),
+            pallet_tfgrid::Farms::contains_key(node.farm_id),
             Error::<T>::FarmNotExists
         );
         let mut farm = pallet_tfgrid::Farms::get(node.farm_id);
@@ -815,18 +1668,53 @@ impl<T: Config> Module<T> {
         debug::info!(
             "Number of farm ips {:?}, number of ips to reserve: {:?}",
             farm.public_ips.len(),
-            node_contract.public_ips as usize
+            public_ips
         );
         ensure!(
-            farm.public_ips.len() >= node_contract.public_ips as usize,
+            farm.public_ips.len() >= public_ips,
             Error::<T>::FarmHasNotEnoughPublicIPs
         );
 
-        let mut ips = Vec::new();
-        for i in 0..farm.public_ips.len() {
-            let mut ip = farm.public_ips[i].clone();
+        let mut ips = node_contract.public_ips_list.clone();
+        let mut ips_reserved = Vec::new();
+        for (index, requested_ip) in requested_ips.iter().enumerate() {
+            ensure!(
+                !requested_ips[..index].contains(requested_ip),
+                Error::<T>::RequestedIPNotAvailable
+            );
+            if ips.iter().any(|ip| ip.ip == *requested_ip) {
+                continue;
+            }
+
+            // requested ips must be free on the farm
+            match farm
+                .public_ips
+                .iter_mut()
+                .find(|ip| ip.ip == *requested_ip && ip.contract_id == 0)
+            {
+                Some(ip) => {
+                    ip.contract_id = contract_id;
+                    ips.push(ip.clone());
+                    ips_reserved.push(ip.clone());
+                }
+                None => return Err(Error::<T>::RequestedIPNotAvailable.into()),
+            }
+        }
+
+        // free the ips that are no longer needed, the most recently reserved first
+        let mut ips_freed = Vec::new();
+        while ips.len() > public_ips {
+            let index = ips
+                .iter()
+                .rposition(|ip| !requested_ips.contains(&ip.ip))
+                .ok_or(Error::<T>::FailedToFreeIPs)?;
+            let ip = ips.remove(index);
+            Self::_release_ip(&mut farm, &ip.ip);
+            ips_freed.push(ip.ip);
+        }
 
-            if ips.len() == node_contract.public_ips as usize {
+        for ip in farm.public_ips.iter_mut() {
+            if ips.len() == public_ips {
                 break;
             }
 
@@ -834,14 +1722,14 @@ impl<T: Config> Module<T> {
             // reserve it now
             if ip.contract_id == 0 {
                 ip.contract_id = contract_id;
-                farm.public_ips[i] = ip.clone();
-                ips.push(ip);
+                ips.push(ip.clone());
+                ips_reserved.push(ip.clone());
             }
         }
 
         // Safeguard check if we actually have the amount of ips we wanted to reserve
         ensure!(
-            ips.len() == node_contract.public_ips as usize,
+            ips.len() == public_ips,
             Error::<T>::FarmHasNotEnoughPublicIPsFree
         );
 
@@ -850,6 +1738,13 @@ impl<T: Config> Module<T> {
 
         node_contract.public_ips_list = ips;
 
+        if !ips_reserved.is_empty() {
+            Self::deposit_event(RawEvent::IPsReserved(contract_id, ips_reserved));
+        }
+        if !ips_freed.is_empty() {
+            Self::deposit_event(RawEvent::IPsFreed(contract_id, ips_freed));
+        }
+
         Ok(())
     }
 
@@ -862,17 +1757,14 @@ impl<T: Config> Module<T> {
         );
         let mut farm = pallet_tfgrid::Farms::get(node.farm_id);
 
-        let mut ips_freed = Vec::new();
-        for i in 0..farm.public_ips.len() {
-            let mut ip = farm.public_ips[i].clone();
-
-            // if an ip has contract id 0 it means it's not reserved
-            // reserve it now
-            if ip.contract_id == contract_id {
-                ip.contract_id = 0;
-                farm.public_ips[i] = ip.clone();
-                ips_freed.push(ip.ip);
-            }
+        let ips_freed: Vec<Vec<u8>> = farm
+            .public_ips
+            .iter()
+            .filter(|ip| ip.contract_id == contract_id)
+            .map(|ip| ip.ip.clone())
+            .collect();
+        for ip in &ips_freed {
+            Self::_release_ip(&mut farm, ip);
         }
 
         pallet_tfgrid::Farms::insert(farm.id, farm);
@@ -883,33 +1775,101 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
-    // Registers a DNS name for a Twin
-    // Ensures uniqueness and also checks if it's a valid DNS name
-    pub fn _create_name_contract(source: T::AccountId, name: Vec<u8>) -> DispatchResult {
+    // Makes a reserved ip of the farm available again, ips the farmer retired while they were
+    // reserved are removed from the farm instead
+    fn _release_ip(farm: &mut pallet_tfgrid_types::Farm, ip: &[u8]) {
+        if pallet_tfgrid::FarmIPsRetiring::contains_key(farm.id, ip) {
+            farm.public_ips.retain(|public_ip| public_ip.ip != ip);
+            pallet_tfgrid::FarmIPsRetiring::remove(farm.id, ip);
+        } else if let Some(public_ip) = farm.public_ips.iter_mut().find(|public_ip| public_ip.ip == ip) {
+            public_ip.contract_id = 0;
+        }
+    }
+
+    // Reserves the requested ipv6 allocation in the first prefix of the farm that has room for it
+    pub fn _reserve_ipv6(
+        contract_id: u64,
+        node_contract: &mut types::NodeContract,
+    ) -> DispatchResult {
+        if node_contract.public_ipv6_prefix_length == 0 {
+            return Ok(());
+        }
+        let node = pallet_tfgrid::Nodes::get(node_contract.node_id);
+
         ensure!(
-            pallet_tfgrid::TwinIdByAccountID::<T>::contains_key(&source),
-            Error::<T>::TwinNotExists
+            pallet_tfgrid::Farms::contains_key(node.farm_id),
+            Error::<T>::FarmNotExists
         );
-        let twin_id = pallet_tfgrid::TwinIdByAccountID::<T>::get(&source);
+        let mut farm = pallet_tfgrid::Farms::get(node.farm_id);
+
+        let allocation = farm
+            .public_ipv6_prefixes
+            .iter_mut()
+            .find_map(|prefix| prefix.allocate(node_contract.public_ipv6_prefix_length, contract_id))
+            .ok_or(Error::<T>::FarmHasNotEnoughPublicIPv6Free)?;
+
+        // Update the farm with the reserved allocation
+        pallet_tfgrid::Farms::insert(farm.id, farm);
+
+        node_contract.public_ipv6_list = vec![allocation.clone()];
+
+        // Emit an event containing the ipv6 allocation reserved for this contract
+        Self::deposit_event(RawEvent::IPv6Reserved(contract_id, vec![allocation.prefix]));
+
+        Ok(())
+    }
+
+    pub fn _free_ipv6(contract_id: u64, node_contract: &mut types::NodeContract) -> DispatchResult {
+        let node = pallet_tfgrid::Nodes::get(node_contract.node_id);
 
-        // Validate name uniqueness
         ensure!(
-            !ContractIDByNameRegistration::contains_key(&name),
-            Error::<T>::NameExists
+            pallet_tfgrid::Farms::contains_key(node.farm_id),
+            Error::<T>::FarmNotExists
         );
+        let mut farm = pallet_tfgrid::Farms::get(node.farm_id);
 
-        for character in &name {
-            match character {
-                c if *c == 45 => (),
-                c if *c >= 48 && *c <= 57 => (),
-                c if *c >= 65 && *c <= 122 => (),
-                _ => return Err(DispatchError::from(Error::<T>::NameNotValid)),
+        let mut ips_freed = Vec::new();
+        for prefix in farm.public_ipv6_prefixes.iter_mut() {
+            for allocation in prefix.allocations.iter().filter(|allocation| allocation.contract_id == contract_id) {
+                ips_freed.push(allocation.prefix.clone());
             }
+            prefix.allocations.retain(|allocation| allocation.contract_id != contract_id);
         }
-        let name_contract = types::NameContract { name: name.clone() };
+
+        pallet_tfgrid::Farms::insert(farm.id, farm);
+
+        // Emit an event containing the ipv6 allocations freed for this contract
+        Self::deposit_event(RawEvent::IPv6Freed(contract_id, ips_freed));
+
+        Ok(())
+    }
+
+    // Registers a DNS name for a Twin
+    // Ensures uniqueness and also checks if it's a valid DNS name
+    pub fn _create_name_contract(twin_id: u32, name: Vec<u8>) -> DispatchResult {
+        Self::_ensure_twin_has_no_debt(twin_id)?;
+
+        // names are case insensitive, they are registered in lowercase
+        let name = Self::_normalize_name(name);
+        Self::_validate_dns_label(&name)?;
+        ensure!(!ReservedNames::get(&name), Error::<T>::NameReserved);
+
+        // Validate name uniqueness
+        ensure!(
+            !ContractIDByNameRegistration::contains_key(&name),
+            Error::<T>::NameExists
+        );
+        let name_contract = types::NameContract {
+            name: name.clone(),
+            pricing_policy_id: NameContractPricingPolicyID::get(),
+        };
         let contract =
             Self::_create_contract(twin_id, types::ContractData::NameContract(name_contract))?;
 
+        // Start billing frequency loop
+        // Will always be block now + frequency
+        Self::_reinsert_contract_to_bill(contract.contract_id);
+
         ContractIDByNameRegistration::insert(name, &contract.contract_id);
 
         Self::deposit_event(RawEvent::ContractCreated(contract));
@@ -917,6 +1877,132 @@ impl<T: Config> Module<T> {
         Ok(())
     }
 
+    // Binds a domain owned by the twin to a gateway, the gateway verifies the ownership of the domain
+    // by checking the challenge published in DNS before the contract is billed
+    pub fn _create_domain_contract(twin_id: u32, gateway_id: u32, domain: Vec<u8>) -> DispatchResult {
+        Self::_ensure_twin_has_no_debt(twin_id)?;
+        ensure!(
+            pallet_tfgrid::Gateways::<T>::contains_key(gateway_id),
+            Error::<T>::GatewayNotExists
+        );
+        // domains are case insensitive, they are bound and challenged in lowercase
+        let domain = Self::_normalize_name(domain);
+        ensure!(Self::_is_valid_domain(&domain), Error::<T>::DomainNotValid);
+        ensure!(
+            !ContractIDByDomain::contains_key(&domain),
+            Error::<T>::DomainExists
+        );
+
+        let id = ContractID::get() + 1;
+        let domain_contract = types::DomainContract {
+            domain: domain.clone(),
+            gateway_id,
+            challenge: Self::_domain_challenge(id, twin_id, &domain),
+        };
+        let contract =
+            Self::_create_contract(twin_id, types::ContractData::DomainContract(domain_contract))?;
+
+        ContractIDByDomain::insert(domain, contract.contract_id);
+
+        // billing only starts once the gateway verified the domain,
+        // if it doesn't within the deployment timeout the contract is canceled
+        Self::_schedule_deployment_deadline(contract.contract_id);
+
+        Self::deposit_event(RawEvent::ContractCreated(contract));
+
+        Ok(())
+    }
+
+    // Called by the gateway once it found the challenge of the contract in the TXT record of the domain
+    pub fn _verify_domain_contract(account_id: T::AccountId, contract_id: u64) -> DispatchResult {
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+        let mut contract = Contracts::get(contract_id);
+        let domain_contract = Self::get_domain_contract(&contract)?;
+        let gateway = pallet_tfgrid::Gateways::<T>::get(domain_contract.gateway_id);
+        ensure!(
+            gateway.account_id == account_id,
+            Error::<T>::GatewayNotAuthorizedToVerifyDomain
+        );
+        ensure!(
+            contract.state == types::ContractState::Created,
+            Error::<T>::ContractNotAwaitingDeployment
+        );
+
+        Self::_update_contract_state(&mut contract, &types::ContractState::Deployed)?;
+
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        ContractLastBilledAt::insert(contract_id, now);
+
+        // Start billing frequency loop
+        // Will always be block now + frequency
+        Self::_reinsert_contract_to_bill(contract_id);
+
+        Self::deposit_event(RawEvent::ContractDeployed(contract_id, account_id));
+
+        Ok(())
+    }
+
+    // A domain is a lowercased fully qualified domain name of at most 253 characters,
+    // with at least 2 labels and a top level domain that is not numeric
+    fn _is_valid_domain(domain: &[u8]) -> bool {
+        if domain.is_empty() || domain.len() > 253 {
+            return false;
+        }
+        let labels: Vec<&[u8]> = domain.split(|c| *c == b'.').collect();
+        if labels.len() < 2 || !labels.iter().all(|label| Self::_validate_dns_label(label).is_ok()) {
+            return false;
+        }
+        !labels[labels.len() - 1].iter().all(|c| c.is_ascii_digit())
+    }
+
+    // A DNS label (RFC 1123) has 1 to 63 lowercase letters, digits or hyphens
+    // and does not start or end with a hyphen
+    fn _validate_dns_label(label: &[u8]) -> DispatchResult {
+        ensure!(!label.is_empty(), Error::<T>::NameTooShort);
+        ensure!(label.len() <= 63, Error::<T>::NameTooLong);
+        ensure!(
+            label
+                .iter()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == b'-'),
+            Error::<T>::NameNotValid
+        );
+        ensure!(
+            label[0] != b'-' && label[label.len() - 1] != b'-',
+            Error::<T>::NameHasLeadingOrTrailingHyphen
+        );
+        Ok(())
+    }
+
+    fn _normalize_name(mut name: Vec<u8>) -> Vec<u8> {
+        name.make_ascii_lowercase();
+        name
+    }
+
+    // The challenge is the hex encoded hash of the contract id, twin id and domain
+    fn _domain_challenge(contract_id: u64, twin_id: u32, domain: &[u8]) -> Vec<u8> {
+        let hash = sp_io::hashing::blake2_256(&(contract_id, twin_id, domain).encode());
+        let mut challenge = Vec::new();
+        for byte in hash.iter() {
+            for nibble in &[byte >> 4, byte & 0xf] {
+                challenge.push(match nibble {
+                    0..=9 => b'0' + nibble,
+                    _ => b'a' + nibble - 10,
+                });
+            }
+        }
+        challenge
+    }
+
+    fn get_domain_contract(contract: &types::Contract) -> Result<types::DomainContract, DispatchError> {
+        match contract.contract_type.clone() {
+            types::ContractData::DomainContract(c) => Ok(c),
+            _ => Err(DispatchError::from(Error::<T>::InvalidContractType)),
+        }
+    }
+
     fn get_node_contract(contract: &types::Contract) -> Result<types::NodeContract, DispatchError> {
         match contract.contract_type.clone() {
             types::ContractData::NodeContract(c) => Ok(c),
@@ -924,10 +2010,179 @@ impl<T: Config> Module<T> {
         }
     }
 
-    // fn get_name_contract(contract: &types::Contract) -> Result<types::NameContract, DispatchError> {
-    // 	match contract.contract_type.clone() {
-    // 		types::ContractData::NameContract(c) => Ok(c),
-    // 		_ => return Err(DispatchError::from(Error::<T>::InvalidContractType)),
-    // 	}
-    // }
+    fn get_name_contract(contract: &types::Contract) -> Result<types::NameContract, DispatchError> {
+        match contract.contract_type.clone() {
+            types::ContractData::NameContract(c) => Ok(c),
+            _ => Err(DispatchError::from(Error::<T>::InvalidContractType)),
+        }
+    }
+
+    // Returns a name contract that is owned by the twin and is not canceled
+    fn _get_name_contract_of_twin(contract_id: u64, twin_id: u32) -> Result<types::Contract, DispatchError> {
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+        let contract = Contracts::get(contract_id);
+        Self::get_name_contract(&contract)?;
+        ensure!(
+            contract.twin_id == twin_id,
+            Error::<T>::TwinNotAuthorizedToUpdateContract
+        );
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+        Ok(contract)
+    }
+
+    pub fn _accept_name_contract_transfer(twin_id: u32, contract_id: u64) -> DispatchResult {
+        ensure!(
+            NameContractTransferOffers::contains_key(contract_id),
+            Error::<T>::NameContractTransferNotOffered
+        );
+        ensure!(
+            NameContractTransferOffers::get(contract_id) == twin_id,
+            Error::<T>::NameContractTransferNotAuthorized
+        );
+        Self::_ensure_twin_has_no_debt(twin_id)?;
+
+        // the previous owner pays for the name up to the transfer
+        let mut contract = Contracts::get(contract_id);
+        Self::_bill_name_contract(&mut contract)?;
+
+        // the previous owner could not pay, which canceled the contract and its offer
+        let mut contract = Contracts::get(contract_id);
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+
+        NameContractTransferOffers::remove(contract_id);
+
+        let previous_twin_id = contract.twin_id;
+        contract.twin_id = twin_id;
+        Contracts::insert(contract_id, &contract);
+
+        // the new owner is billed from the transfer on
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        ContractLastBilledAt::insert(contract_id, now);
+
+        Self::deposit_event(RawEvent::NameContractTransferred(contract_id, previous_twin_id, twin_id));
+        Self::deposit_event(RawEvent::ContractUpdated(contract));
+
+        Ok(())
+    }
+
+    pub fn _update_name_contract_pricing_policy(contract_id: u64, pricing_policy_id: u32) -> DispatchResult {
+        ensure!(
+            Contracts::contains_key(contract_id),
+            Error::<T>::ContractNotExists
+        );
+        ensure!(
+            pallet_tfgrid::PricingPolicies::<T>::contains_key(pricing_policy_id),
+            Error::<T>::PricingPolicyNotExists
+        );
+
+        let mut contract = Contracts::get(contract_id);
+        ensure!(!contract.is_state_delete(), Error::<T>::ContractIsCanceled);
+        let mut name_contract = Self::get_name_contract(&contract)?;
+
+        // the time since the last bill is still billed at the old pricing policy
+        Self::_accrue_unbilled_cost(&contract)?;
+
+        name_contract.pricing_policy_id = pricing_policy_id;
+        contract.contract_type = types::ContractData::NameContract(name_contract);
+        Contracts::insert(contract_id, &contract);
+
+        Self::deposit_event(RawEvent::ContractUpdated(contract));
+
+        Ok(())
+    }
+
+    fn migrate_storage() -> Weight {
+        let version = PalletVersion::get();
+        if version == types::PalletStorageVersion::V4 {
+            return 0;
+        }
+
+        debug::info!(" >>> Migrating smart contract storage from {:?} to V4", version);
+
+        // V1 to V3 are the versions from before this pallet had migrations, they all hold
+        // the original contract layout
+        let mut reads_writes = 0;
+        Contracts::translate::<types::deprecated::ContractV1, _>(|_, contract| {
+            reads_writes += 1;
+
+            let contract_type = match contract.contract_type {
+                types::deprecated::ContractDataV1::NodeContract(node_contract) => {
+                    types::ContractData::NodeContract(types::NodeContract {
+                        node_id: node_contract.node_id,
+                        deployment_data: node_contract.deployment_data,
+                        deployment_hash: node_contract.deployment_hash,
+                        public_ips: node_contract.public_ips,
+                        public_ips_list: node_contract.public_ips_list,
+                        public_ipv6_prefix_length: 0,
+                        public_ipv6_list: Vec::new(),
+                    })
+                }
+                types::deprecated::ContractDataV1::NameContract(name_contract) => {
+                    Self::_migrate_name_contract(name_contract)
+                }
+            };
+
+            let mut contract = types::Contract {
+                version: CONTRACT_VERSION,
+                state: contract.state,
+                contract_id: contract.contract_id,
+                twin_id: contract.twin_id,
+                contract_type,
+            };
+            contract.state = Self::_deployed_state(&contract);
+            Some(contract)
+        });
+
+        // names are case insensitive, registrations move to their lowercase name. A name that is
+        // already registered in lowercase keeps it, otherwise the oldest contract gets it and names
+        // that only differ in case from it keep their registration as is. Names that do not follow
+        // the DNS label rules are kept, they can't be registered again once released.
+        let mut registrations: Vec<(Vec<u8>, u64)> = ContractIDByNameRegistration::iter().collect();
+        registrations.sort_by_key(|(_, contract_id)| *contract_id);
+        for (name, contract_id) in registrations {
+            reads_writes += 1;
+            let normalized = Self::_normalize_name(name.clone());
+            if normalized == name || ContractIDByNameRegistration::contains_key(&normalized) {
+                continue;
+            }
+
+            ContractIDByNameRegistration::remove(&name);
+            ContractIDByNameRegistration::insert(&normalized, contract_id);
+            Contracts::mutate(contract_id, |contract| {
+                if let types::ContractData::NameContract(name_contract) = &mut contract.contract_type {
+                    name_contract.name = normalized.clone();
+                }
+            });
+            reads_writes += 2;
+        }
+
+        // seed the discount tiers and certified surcharge that used to be hardcoded
+        DiscountConfiguration::put(types::default_discount_configuration());
+
+        PalletVersion::put(types::PalletStorageVersion::V4);
+
+        T::DbWeight::get().reads_writes(reads_writes + 1, reads_writes + 2)
+    }
+
+    // Name contracts created before they had their own pricing policy were billed with the first one
+    fn _migrate_name_contract(name_contract: types::deprecated::NameContractV1) -> types::ContractData {
+        types::ContractData::NameContract(types::NameContract {
+            name: name_contract.name,
+            pricing_policy_id: 1,
+        })
+    }
+
+    // Node contracts created before nodes confirmed deployments are already being billed,
+    // so they are considered deployed
+    fn _deployed_state(contract: &types::Contract) -> types::ContractState {
+        match contract.contract_type {
+            types::ContractData::NodeContract(_) if contract.state == types::ContractState::Created => {
+                types::ContractState::Deployed
+            }
+            _ => contract.state.clone(),
+        }
+    }
 }
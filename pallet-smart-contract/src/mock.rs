@@ -46,6 +46,8 @@ parameter_types! {
         frame_system::limits::BlockWeights::simple_max(1024);
     pub const ExistentialDeposit: u64 = 1;
     pub StakingPoolAccount: AccountId = get_staking_pool_account();
+    pub const HeartbeatWindow: u64 = 7200;
+    pub const UptimePeriod: u64 = 3600;
 }
 
 impl frame_system::Config for TestRuntime {
@@ -87,6 +89,8 @@ impl pallet_tfgrid::Config for TestRuntime {
     type Event = Event;
     type Currency = Balances;
     type RestrictedOrigin = EnsureRoot<Self::AccountId>;
+    type HeartbeatWindow = HeartbeatWindow;
+    type UptimePeriod = UptimePeriod;
 }
 
 impl pallet_tft_price::Config for TestRuntime {
@@ -104,6 +108,8 @@ impl pallet_timestamp::Config for TestRuntime {
 
 parameter_types! {
     pub const BillingFrequency: u64 = 10;
+    pub const DeploymentTimeout: u64 = 20;
+    pub const GracePeriod: u64 = 20;
 }
 
 impl Config for TestRuntime {
@@ -111,6 +117,8 @@ impl Config for TestRuntime {
     type Currency = Balances;
     type StakingPoolAccount = StakingPoolAccount;
     type BillingFrequency = BillingFrequency;
+    type DeploymentTimeout = DeploymentTimeout;
+    type GracePeriod = GracePeriod;
 }
 
 type AccountPublic = <MultiSignature as Verify>::Signer;
@@ -2,10 +2,14 @@ use crate::{mock::*, Error, RawEvent};
 use substrate_fixed::types::{U16F16};
 use frame_support::{
     assert_noop, assert_ok,
+    storage::{unhashed, StorageDoubleMap, StorageMap, StorageValue},
     traits::{OnFinalize, OnInitialize},
 };
 use frame_system::RawOrigin;
-use sp_runtime::traits::SaturatedConversion;
+use sp_runtime::{
+    traits::{BadOrigin, SaturatedConversion},
+    Perbill,
+};
 
 use super::types;
 use pallet_tfgrid::types as pallet_tfgrid_types;
@@ -20,7 +24,8 @@ fn test_create_contract_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
     });
 }
@@ -35,7 +40,8 @@ fn test_create_node_contract_with_public_ips_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            1
+            1,
+            Vec::new()
         ));
 
         let node_contract = SmartContractModule::contracts(1);
@@ -46,7 +52,7 @@ fn test_create_node_contract_with_public_ips_works() {
                 assert_eq!(farm.public_ips[0].contract_id, 1);
 
                 assert_eq!(c.public_ips, 1);
-                assert_eq!(c.public_ips_list[0].ip, "1.1.1.0".as_bytes().to_vec());
+                assert_eq!(c.public_ips_list[0].ip, "185.206.122.33/24".as_bytes().to_vec());
             }
             _ => (),
         }
@@ -64,13 +70,47 @@ fn test_create_contract_with_undefined_node_fails() {
                 2,
                 "some_data".as_bytes().to_vec(),
                 "hash".as_bytes().to_vec(),
-                0
+                0,
+                Vec::new()
             ),
             Error::<TestRuntime>::NodeNotExists
         );
     });
 }
 
+#[test]
+fn test_create_contract_on_node_that_is_not_up_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(TfgridModule::set_node_standby(Origin::signed(alice()), 1));
+        assert_noop!(
+            SmartContractModule::create_node_contract(
+                Origin::signed(alice()),
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                0,
+                Vec::new()
+            ),
+            Error::<TestRuntime>::NodeNotAvailableToDeploy
+        );
+
+        assert_ok!(TfgridModule::decommission_node(Origin::signed(alice()), 1));
+        assert_noop!(
+            SmartContractModule::create_node_contract(
+                Origin::signed(alice()),
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                0,
+                Vec::new()
+            ),
+            Error::<TestRuntime>::NodeNotAvailableToDeploy
+        );
+    });
+}
+
 #[test]
 fn test_create_contract_with_same_hash_and_node_fails() {
     new_test_ext().execute_with(|| {
@@ -81,7 +121,8 @@ fn test_create_contract_with_same_hash_and_node_fails() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_noop!(
@@ -90,7 +131,8 @@ fn test_create_contract_with_same_hash_and_node_fails() {
                 1,
                 "some_data".as_bytes().to_vec(),
                 "hash".as_bytes().to_vec(),
-                0
+                0,
+                Vec::new()
             ),
             Error::<TestRuntime>::ContractIsNotUnique
         );
@@ -107,7 +149,8 @@ fn test_create_contract_which_was_canceled_before_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
         let contract_id = SmartContractModule::node_contract_by_hash(1, "hash".as_bytes().to_vec());
         assert_eq!(contract_id, 1);
@@ -122,7 +165,8 @@ fn test_create_contract_which_was_canceled_before_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
         let contract_id = SmartContractModule::node_contract_by_hash(1, "hash".as_bytes().to_vec());
         assert_eq!(contract_id, 2);
@@ -139,14 +183,17 @@ fn test_update_contract_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_ok!(SmartContractModule::update_node_contract(
             Origin::signed(alice()),
             1,
             "no_data".as_bytes().to_vec(),
-            "some_other_hash".as_bytes().to_vec()
+            "some_other_hash".as_bytes().to_vec(),
+            None,
+            Vec::new()
         ));
 
         let node_contract = types::NodeContract {
@@ -155,6 +202,8 @@ fn test_update_contract_works() {
             deployment_hash: "some_other_hash".as_bytes().to_vec(),
             public_ips: 0,
             public_ips_list: Vec::new(),
+            public_ipv6_prefix_length: 0,
+            public_ipv6_list: Vec::new(),
         };
         let contract_type = types::ContractData::NodeContract(node_contract);
 
@@ -162,7 +211,7 @@ fn test_update_contract_works() {
             contract_id: 1,
             state: types::ContractState::Created,
             twin_id: 1,
-            version: 1,
+            version: crate::CONTRACT_VERSION,
             contract_type,
         };
 
@@ -190,7 +239,9 @@ fn test_update_contract_not_exists_fails() {
                 Origin::signed(alice()),
                 1,
                 "some_data".as_bytes().to_vec(),
-                "hash".as_bytes().to_vec()
+                "hash".as_bytes().to_vec(),
+                None,
+                Vec::new()
             ),
             Error::<TestRuntime>::ContractNotExists
         );
@@ -207,7 +258,8 @@ fn test_update_contract_wrong_twins_fails() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_noop!(
@@ -215,13 +267,52 @@ fn test_update_contract_wrong_twins_fails() {
                 Origin::signed(bob()),
                 1,
                 "some_data".as_bytes().to_vec(),
-                "hash".as_bytes().to_vec()
+                "hash".as_bytes().to_vec(),
+                None,
+                Vec::new()
             ),
             Error::<TestRuntime>::TwinNotAuthorizedToUpdateContract
         );
     });
 }
 
+#[test]
+fn test_update_canceled_contract_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(alice()),
+            1
+        ));
+
+        assert_noop!(
+            SmartContractModule::update_node_contract(
+                Origin::signed(alice()),
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                Some(1),
+                Vec::new()
+            ),
+            Error::<TestRuntime>::ContractIsCanceled
+        );
+        assert_noop!(
+            SmartContractModule::reserve_node_contract_ipv6(Origin::signed(alice()), 1, 64),
+            Error::<TestRuntime>::ContractIsCanceled
+        );
+    });
+}
+
 #[test]
 fn test_cancel_contract_works() {
     new_test_ext().execute_with(|| {
@@ -232,7 +323,8 @@ fn test_cancel_contract_works() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_ok!(SmartContractModule::cancel_contract(
@@ -246,6 +338,8 @@ fn test_cancel_contract_works() {
             deployment_hash: "hash".as_bytes().to_vec(),
             public_ips: 0,
             public_ips_list: Vec::new(),
+            public_ipv6_prefix_length: 0,
+            public_ipv6_list: Vec::new(),
         };
         let contract_type = types::ContractData::NodeContract(node_contract);
 
@@ -253,7 +347,7 @@ fn test_cancel_contract_works() {
             contract_id: 1,
             state: types::ContractState::Deleted(types::Cause::CanceledByUser),
             twin_id: 1,
-            version: 1,
+            version: crate::CONTRACT_VERSION,
             contract_type,
         };
 
@@ -272,7 +366,7 @@ fn test_cancel_name_contract_works() {
 
         assert_ok!(SmartContractModule::create_name_contract(
             Origin::signed(alice()),
-            "some_name".as_bytes().to_vec()
+            "some-name".as_bytes().to_vec()
         ));
 
         assert_ok!(SmartContractModule::cancel_contract(
@@ -281,7 +375,8 @@ fn test_cancel_name_contract_works() {
         ));
 
         let name_contract = types::NameContract {
-            name: "some_name".as_bytes().to_vec(),
+            name: "some-name".as_bytes().to_vec(),
+            pricing_policy_id: 1,
         };
         let contract_type = types::ContractData::NameContract(name_contract);
 
@@ -289,7 +384,7 @@ fn test_cancel_name_contract_works() {
             contract_id: 1,
             state: types::ContractState::Deleted(types::Cause::CanceledByUser),
             twin_id: 1,
-            version: 1,
+            version: crate::CONTRACT_VERSION,
             contract_type,
         };
 
@@ -297,7 +392,7 @@ fn test_cancel_name_contract_works() {
         assert_eq!(name_contract, expected_contract_value);
 
         let contract_id =
-            SmartContractModule::contract_id_by_name_registration("some_name".as_bytes().to_vec());
+            SmartContractModule::contract_id_by_name_registration("some-name".as_bytes().to_vec());
         assert_eq!(contract_id, 0);
     });
 }
@@ -312,7 +407,8 @@ fn test_create_multiple_contracts_work() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash1".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_ok!(SmartContractModule::create_node_contract(
@@ -320,7 +416,8 @@ fn test_create_multiple_contracts_work() {
             1,
             "some_data2".as_bytes().to_vec(),
             "hash2".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         assert_ok!(SmartContractModule::create_node_contract(
@@ -328,7 +425,8 @@ fn test_create_multiple_contracts_work() {
             1,
             "some_data3".as_bytes().to_vec(),
             "hash3".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
 
         let node_contracts = SmartContractModule::active_node_contracts(1);
@@ -346,45 +444,87 @@ fn test_create_multiple_contracts_work() {
 }
 
 #[test]
-fn test_cancel_contract_works_public_ips_frees_ip() {
+fn test_cancel_contract_twice_fails() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
 
-        assert_ok!(SmartContractModule::create_node_contract(
+        assert_ok!(SmartContractModule::create_name_contract(
             Origin::signed(alice()),
-            1,
-            "some_data".as_bytes().to_vec(),
-            "hash".as_bytes().to_vec(),
-            1
+            "foobar".as_bytes().to_vec()
         ));
 
-        let farm = TfgridModule::farms(1);
-        assert_eq!(farm.public_ips[0].contract_id, 1);
-
         assert_ok!(SmartContractModule::cancel_contract(
             Origin::signed(alice()),
             1
         ));
 
-        let farm = TfgridModule::farms(1);
-        assert_eq!(farm.public_ips[0].contract_id, 0);
+        // the name can be registered again by someone else
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+
+        assert_noop!(
+            SmartContractModule::cancel_contract(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::ContractIsCanceled
+        );
+        assert_eq!(
+            SmartContractModule::contract_id_by_name_registration("foobar".as_bytes().to_vec()),
+            2
+        );
     });
 }
 
 #[test]
-fn test_cancel_contract_not_exists_fails() {
+fn test_cancel_contract_final_bill_is_retried_without_accruing() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
+        run_to_block(1);
 
-        assert_noop!(
-            SmartContractModule::cancel_contract(Origin::signed(alice()), 1),
-            Error::<TestRuntime>::ContractNotExists
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+        run_to_block(6);
+
+        // the tft price is not set yet, so the final bill fails
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(bob()), 1));
+        let amount_unbilled = SmartContractModule::contract_billing_information_by_id(1).amount_unbilled;
+        assert_ne!(amount_unbilled, 0);
+        assert_eq!(SmartContractModule::contract_billed_at(1), 1628082000 + 6 * 6);
+
+        // a failed retry does not accrue the public ip past the cancellation
+        run_to_block(12);
+        assert_eq!(
+            SmartContractModule::contract_billing_information_by_id(1).amount_unbilled,
+            amount_unbilled
         );
+
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+        run_to_block(22);
+        assert_eq!(SmartContractModule::contract_billing_information_by_id(1).amount_unbilled, 0);
+
+        // with nothing left to bill the contract leaves the billing schedule
+        run_to_block(32);
+        assert!(!crate::ContractNextBillingBlock::contains_key(1));
+
+        let bills = System::events()
+            .into_iter()
+            .filter(|r| {
+                matches!(r.event, Event::pallet_smart_contract(RawEvent::ContractBilled(ref bill)) if bill.contract_id == 1)
+            })
+            .count();
+        assert_eq!(bills, 1);
     });
 }
 
 #[test]
-fn test_cancel_contract_wrong_twins_fails() {
+fn test_cancel_contract_works_public_ips_frees_ip() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
 
@@ -393,122 +533,1184 @@ fn test_cancel_contract_wrong_twins_fails() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            1,
+            Vec::new()
         ));
 
-        assert_noop!(
-            SmartContractModule::cancel_contract(Origin::signed(bob()), 1),
-            Error::<TestRuntime>::TwinNotAuthorizedToCancelContract
-        );
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 1);
+
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(alice()),
+            1
+        ));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
     });
 }
 
 #[test]
-fn test_create_name_contract() {
+fn test_create_node_contract_with_requested_ips_works() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
+        add_farm_ips();
 
-        assert_ok!(SmartContractModule::create_name_contract(
-            Origin::signed(bob()),
-            "foobar".as_bytes().to_vec()
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            2,
+            vec!["185.206.122.41/24".as_bytes().to_vec()]
+        ));
+
+        let node_contract = get_node_contract(1);
+        let ips: Vec<Vec<u8>> = node_contract.public_ips_list.into_iter().map(|ip| ip.ip).collect();
+        assert_eq!(
+            ips,
+            vec![
+                "185.206.122.41/24".as_bytes().to_vec(),
+                "185.206.122.33/24".as_bytes().to_vec()
+            ]
+        );
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 1);
+        assert_eq!(farm.public_ips[1].contract_id, 0);
+        assert_eq!(farm.public_ips[2].contract_id, 1);
+
+        // the ip can be acquired again by a new contract once it is freed
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(alice()),
+            1
+        ));
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "other_hash".as_bytes().to_vec(),
+            1,
+            vec!["185.206.122.41/24".as_bytes().to_vec()]
         ));
+        assert_eq!(TfgridModule::farms(1).public_ips[2].contract_id, 2);
     });
 }
 
 #[test]
-fn test_create_name_contract_double_with_same_name_fails() {
+fn test_create_node_contract_with_unavailable_requested_ips_fails() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
+        add_farm_ips();
 
-        assert_ok!(SmartContractModule::create_name_contract(
-            Origin::signed(bob()),
-            "foobar".as_bytes().to_vec()
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            vec!["185.206.122.40/24".as_bytes().to_vec()]
         ));
-        assert_noop!(
-            SmartContractModule::create_name_contract(
-                Origin::signed(alice()),
-                "foobar".as_bytes().to_vec()
-            ),
-            Error::<TestRuntime>::NameExists
-        );
+
+        let cases = vec![
+            // reserved by the first contract
+            (1, vec!["185.206.122.40/24"], Error::<TestRuntime>::RequestedIPNotAvailable),
+            // not an ip of the farm
+            (1, vec!["185.206.122.50/24"], Error::<TestRuntime>::RequestedIPNotAvailable),
+            (2, vec!["185.206.122.41/24", "185.206.122.41/24"], Error::<TestRuntime>::RequestedIPNotAvailable),
+            (1, vec!["185.206.122.33/24", "185.206.122.41/24"], Error::<TestRuntime>::TooManyRequestedIPs),
+        ];
+
+        for (public_ips, requested_ips, error) in cases {
+            assert_noop!(
+                SmartContractModule::create_node_contract(
+                    Origin::signed(alice()),
+                    1,
+                    "some_data".as_bytes().to_vec(),
+                    "other_hash".as_bytes().to_vec(),
+                    public_ips,
+                    requested_ips.iter().map(|ip| ip.as_bytes().to_vec()).collect()
+                ),
+                error
+            );
+        }
     });
 }
 
 #[test]
-fn test_recreate_name_contract_after_cancel_works() {
+fn test_update_node_contract_with_requested_ips_works() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
+        add_farm_ips();
 
-        assert_ok!(SmartContractModule::create_name_contract(
-            Origin::signed(bob()),
-            "foobar".as_bytes().to_vec()
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
         ));
+        assert_eq!(TfgridModule::farms(1).public_ips[0].contract_id, 1);
 
-        assert_ok!(SmartContractModule::cancel_contract(
-            Origin::signed(bob()),
-            1
+        assert_ok!(SmartContractModule::update_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            None,
+            vec!["185.206.122.40/24".as_bytes().to_vec()]
         ));
 
-        assert_ok!(SmartContractModule::create_name_contract(
-            Origin::signed(bob()),
-            "foobar".as_bytes().to_vec()
-        ));
+        let node_contract = get_node_contract(1);
+        assert_eq!(node_contract.public_ips, 1);
+        assert_eq!(node_contract.public_ips_list.len(), 1);
+        assert_eq!(node_contract.public_ips_list[0].ip, "185.206.122.40/24".as_bytes().to_vec());
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
+        assert_eq!(farm.public_ips[1].contract_id, 1);
     });
 }
 
 #[test]
-fn test_name_registration_fails_with_invalid_dns_name() {
+fn test_update_node_contract_public_ips_count_works() {
     new_test_ext().execute_with(|| {
+        // events are not recorded in the genesis block
+        System::set_block_number(1);
         prepare_farm_and_node();
+        add_farm_ips();
 
-        assert_noop!(
-            SmartContractModule::create_name_contract(
-                Origin::signed(alice()),
-                "foo.bar".as_bytes().to_vec()
-            ),
-            Error::<TestRuntime>::NameNotValid
-        );
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+
+        assert_ok!(SmartContractModule::update_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            Some(3),
+            Vec::new()
+        ));
+        let node_contract = get_node_contract(1);
+        assert_eq!(node_contract.public_ips, 3);
+        assert_eq!(node_contract.public_ips_list.len(), 3);
+        assert!(TfgridModule::farms(1).public_ips.iter().all(|ip| ip.contract_id == 1));
 
         assert_noop!(
-            SmartContractModule::create_name_contract(
+            SmartContractModule::update_node_contract(
                 Origin::signed(alice()),
-                "foo!".as_bytes().to_vec()
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                Some(4),
+                Vec::new()
             ),
-            Error::<TestRuntime>::NameNotValid
+            Error::<TestRuntime>::FarmHasNotEnoughPublicIPs
         );
-
+        // a failed update with a new hash leaves the contract on its old hash
         assert_noop!(
-            SmartContractModule::create_name_contract(
+            SmartContractModule::update_node_contract(
                 Origin::signed(alice()),
-                "foo;'".as_bytes().to_vec()
+                1,
+                "some_data".as_bytes().to_vec(),
+                "other_hash".as_bytes().to_vec(),
+                Some(4),
+                Vec::new()
             ),
-            Error::<TestRuntime>::NameNotValid
+            Error::<TestRuntime>::FarmHasNotEnoughPublicIPs
         );
-
         assert_noop!(
-            SmartContractModule::create_name_contract(
+            SmartContractModule::update_node_contract(
                 Origin::signed(alice()),
-                "foo123.%".as_bytes().to_vec()
+                1,
+                "some_data".as_bytes().to_vec(),
+                "other_hash".as_bytes().to_vec(),
+                Some(3),
+                vec!["185.206.122.99/24".as_bytes().to_vec()]
             ),
-            Error::<TestRuntime>::NameNotValid
+            Error::<TestRuntime>::RequestedIPNotAvailable
         );
-    });
-}
-
-#[test]
-fn test_contract_billing_loop() {
-    new_test_ext().execute_with(|| {
-        prepare_farm_and_node();
-        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 1).unwrap();
-        run_to_block(1);
+        assert_eq!(SmartContractModule::node_contract_by_hash(1, "hash".as_bytes().to_vec()), 1);
+        assert_eq!(SmartContractModule::node_contract_by_hash(1, "other_hash".as_bytes().to_vec()), 0);
+
+        assert_ok!(SmartContractModule::update_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            Some(0),
+            Vec::new()
+        ));
+        let node_contract = get_node_contract(1);
+        assert_eq!(node_contract.public_ips, 0);
+        assert_eq!(node_contract.public_ips_list.len(), 0);
+        assert!(TfgridModule::farms(1).public_ips.iter().all(|ip| ip.contract_id == 0));
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let reserved_ip = |ip: &str| pallet_tfgrid_types::PublicIP {
+            ip: ip.as_bytes().to_vec(),
+            gateway: "185.206.122.1".as_bytes().to_vec(),
+            contract_id: 1,
+        };
+        assert!(our_events.contains(&RawEvent::IPsReserved(1, vec![reserved_ip("185.206.122.33/24")])));
+        assert!(our_events.contains(&RawEvent::IPsReserved(
+            1,
+            vec![reserved_ip("185.206.122.40/24"), reserved_ip("185.206.122.41/24")]
+        )));
+        assert!(our_events.contains(&RawEvent::IPsFreed(
+            1,
+            vec![
+                "185.206.122.41/24".as_bytes().to_vec(),
+                "185.206.122.40/24".as_bytes().to_vec(),
+                "185.206.122.33/24".as_bytes().to_vec()
+            ]
+        )));
+    });
+}
+
+#[test]
+fn test_update_node_contract_public_ips_count_bills_old_count() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        add_farm_ips();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+        run_to_block(6);
+
+        assert_ok!(SmartContractModule::update_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            Some(3),
+            Vec::new()
+        ));
+
+        // 30 seconds of 1 ip at 69400 per hour
+        assert_eq!(SmartContractModule::contract_billing_information_by_id(1).amount_unbilled, 578);
+        assert_eq!(SmartContractModule::contract_billed_at(1), 1628082000 + 6 * 6);
+    });
+}
+
+#[test]
+fn test_retired_ip_is_removed_when_contract_is_canceled() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+
+        let ip = "185.206.122.33/24".as_bytes().to_vec();
+        assert_noop!(
+            TfgridModule::remove_farm_ip(Origin::signed(alice()), 1, ip.clone()),
+            pallet_tfgrid::Error::<TestRuntime>::CannotRemoveReservedIP
+        );
+
+        assert_ok!(TfgridModule::retire_farm_ip(Origin::signed(alice()), 1, ip.clone()));
+        // the ip stays on the farm as long as the contract uses it
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips.len(), 1);
+        assert!(TfgridModule::farm_ips_retiring(1, &ip));
+
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(alice()),
+            1
+        ));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips.len(), 0);
+        assert!(!TfgridModule::farm_ips_retiring(1, &ip));
+    });
+}
+
+#[test]
+fn test_reserve_node_contract_ipv6_works() {
+    new_test_ext().execute_with(|| {
+        // events are not recorded in the genesis block
+        System::set_block_number(1);
+        prepare_farm_and_node();
+
+        assert_ok!(TfgridModule::add_farm_ipv6_prefix(
+            Origin::signed(alice()),
+            1,
+            "2a02:1802:5e::/48".as_bytes().to_vec(),
+            "2a02:1802:5e::1".as_bytes().to_vec()
+        ));
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::reserve_node_contract_ipv6(
+            Origin::signed(alice()),
+            1,
+            64
+        ));
+
+        let node_contract = get_node_contract(1);
+        assert_eq!(node_contract.public_ips_list.len(), 1);
+        assert_eq!(node_contract.public_ipv6_prefix_length, 64);
+        assert_eq!(node_contract.public_ipv6_list.len(), 1);
+        assert_eq!(node_contract.public_ipv6_list[0].prefix, "2a02:1802:5e:1::/64".as_bytes().to_vec());
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ipv6_prefixes[0].allocations[0].contract_id, 1);
+
+        // a contract holds a single ipv6 allocation
+        assert_noop!(
+            SmartContractModule::reserve_node_contract_ipv6(Origin::signed(alice()), 1, 64),
+            Error::<TestRuntime>::NodeContractHasIPv6
+        );
+
+        // the prefix can't be removed while it is in use
+        assert_noop!(
+            TfgridModule::remove_farm_ipv6_prefix(
+                Origin::signed(alice()),
+                1,
+                "2a02:1802:5e::/48".as_bytes().to_vec()
+            ),
+            pallet_tfgrid::Error::<TestRuntime>::PublicIPv6PrefixInUse
+        );
+
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(alice()),
+            1
+        ));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
+        assert_eq!(farm.public_ipv6_prefixes[0].allocations.len(), 0);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events.contains(&RawEvent::IPv6Reserved(
+            1,
+            vec!["2a02:1802:5e:1::/64".as_bytes().to_vec()]
+        )));
+        assert!(our_events.contains(&RawEvent::IPv6Freed(
+            1,
+            vec!["2a02:1802:5e:1::/64".as_bytes().to_vec()]
+        )));
+    });
+}
+
+#[test]
+fn test_reserve_node_contract_ipv6_without_free_ipv6_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+
+        assert_noop!(
+            SmartContractModule::reserve_node_contract_ipv6(Origin::signed(alice()), 1, 128),
+            Error::<TestRuntime>::FarmHasNotEnoughPublicIPv6Free
+        );
+        assert_noop!(
+            SmartContractModule::reserve_node_contract_ipv6(Origin::signed(alice()), 1, 0),
+            Error::<TestRuntime>::InvalidIPv6PrefixLength
+        );
+        assert_noop!(
+            SmartContractModule::reserve_node_contract_ipv6(Origin::signed(bob()), 1, 64),
+            Error::<TestRuntime>::TwinNotAuthorizedToUpdateContract
+        );
+    });
+}
+
+#[test]
+fn test_cancel_contract_not_exists_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_noop!(
+            SmartContractModule::cancel_contract(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::ContractNotExists
+        );
+    });
+}
+
+#[test]
+fn test_cancel_contract_wrong_twins_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(alice()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+
+        assert_noop!(
+            SmartContractModule::cancel_contract(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::TwinNotAuthorizedToCancelContract
+        );
+    });
+}
+
+#[test]
+fn test_delegate_can_create_and_cancel_contracts_for_twin() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_noop!(
+            SmartContractModule::create_node_contract_for_twin(
+                Origin::signed(bob()),
+                1,
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                0,
+                Vec::new()
+            ),
+            Error::<TestRuntime>::TwinNotAuthorizedToCreateContract
+        );
+
+        assert_ok!(TfgridModule::grant_delegation(
+            Origin::signed(alice()),
+            bob(),
+            vec![pallet_tfgrid_types::DelegatePermission::Contracts],
+            None
+        ));
+
+        assert_ok!(SmartContractModule::create_node_contract_for_twin(
+            Origin::signed(bob()),
+            1,
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::create_name_contract_for_twin(
+            Origin::signed(bob()),
+            1,
+            "foobar".as_bytes().to_vec()
+        ));
+        assert_eq!(SmartContractModule::contracts(1).twin_id, 1);
+        assert_eq!(SmartContractModule::contracts(2).twin_id, 1);
+
+        assert_ok!(SmartContractModule::update_node_contract(
+            Origin::signed(bob()),
+            1,
+            "no_data".as_bytes().to_vec(),
+            "some_other_hash".as_bytes().to_vec(),
+            None,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(bob()), 1));
+
+        // once revoked the delegate cannot act on the twin's contracts anymore
+        assert_ok!(TfgridModule::revoke_delegation(Origin::signed(alice()), bob()));
+        assert_noop!(
+            SmartContractModule::cancel_contract(Origin::signed(bob()), 2),
+            Error::<TestRuntime>::TwinNotAuthorizedToCancelContract
+        );
+    });
+}
+
+#[test]
+fn test_create_name_contract() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+    });
+}
+
+#[test]
+fn test_create_name_contract_double_with_same_name_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foobar".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameExists
+        );
+    });
+}
+
+#[test]
+fn test_recreate_name_contract_after_cancel_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+
+        assert_ok!(SmartContractModule::cancel_contract(
+            Origin::signed(bob()),
+            1
+        ));
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+    });
+}
+
+#[test]
+fn test_name_registration_fails_with_invalid_dns_name() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foo.bar".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameNotValid
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foo!".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameNotValid
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foo;'".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameNotValid
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foo123.%".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameNotValid
+        );
+
+        for name in &["foo[bar", "foo_bar", "foo^", "`foo"] {
+            assert_noop!(
+                SmartContractModule::create_name_contract(
+                    Origin::signed(alice()),
+                    name.as_bytes().to_vec()
+                ),
+                Error::<TestRuntime>::NameNotValid
+            );
+        }
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(Origin::signed(alice()), Vec::new()),
+            Error::<TestRuntime>::NameTooShort
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(Origin::signed(alice()), vec![b'a'; 64]),
+            Error::<TestRuntime>::NameTooLong
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "-foo".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameHasLeadingOrTrailingHyphen
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "foo-".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameHasLeadingOrTrailingHyphen
+        );
+    });
+}
+
+#[test]
+fn test_name_registration_is_case_insensitive() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "FooBar".as_bytes().to_vec()
+        ));
+        assert_eq!(
+            SmartContractModule::contract_id_by_name_registration("foobar".as_bytes().to_vec()),
+            1
+        );
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(alice()),
+                "fOObar".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameExists
+        );
+    });
+}
+
+#[test]
+fn test_reserved_names() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_noop!(
+            SmartContractModule::add_reserved_name(Origin::signed(alice()), "admin".as_bytes().to_vec()),
+            BadOrigin
+        );
+        assert_ok!(SmartContractModule::add_reserved_name(
+            RawOrigin::Root.into(),
+            "Admin".as_bytes().to_vec()
+        ));
+        assert!(SmartContractModule::reserved_names("admin".as_bytes().to_vec()));
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(bob()),
+                "admin".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameReserved
+        );
+
+        assert_ok!(SmartContractModule::remove_reserved_name(
+            RawOrigin::Root.into(),
+            "admin".as_bytes().to_vec()
+        ));
+        assert_noop!(
+            SmartContractModule::remove_reserved_name(
+                RawOrigin::Root.into(),
+                "admin".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameNotReserved
+        );
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "admin".as_bytes().to_vec()
+        ));
+    });
+}
+
+#[test]
+fn test_name_contract_transfer_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+
+        assert_ok!(SmartContractModule::offer_name_contract_transfer(
+            Origin::signed(bob()),
+            1,
+            3
+        ));
+        assert_eq!(SmartContractModule::name_contract_transfer_offers(1), 3);
+
+        run_to_block(6);
+        let bob_balance = Balances::free_balance(bob());
+        assert_ok!(SmartContractModule::accept_name_contract_transfer(
+            Origin::signed(charlie()),
+            1
+        ));
+
+        // bob paid for the name up to the transfer, charlie is billed from then on
+        assert!(Balances::free_balance(bob()) < bob_balance);
+        assert_eq!(SmartContractModule::contract_billed_at(1), 1628082036);
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.twin_id, 3);
+        assert_eq!(
+            SmartContractModule::contract_id_by_name_registration("foobar".as_bytes().to_vec()),
+            1
+        );
+        assert_eq!(SmartContractModule::name_contract_transfer_offers(1), 0);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events.contains(&RawEvent::NameContractTransferred(1, 2, 3)));
+
+        // the previous owner can no longer cancel the name
+        assert_noop!(
+            SmartContractModule::cancel_contract(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::TwinNotAuthorizedToCancelContract
+        );
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(charlie()), 1));
+    });
+}
+
+#[test]
+fn test_name_contract_transfer_fails_when_previous_owner_cannot_pay() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(charlie()),
+            "foobar".as_bytes().to_vec()
+        ));
+        assert_ok!(SmartContractModule::offer_name_contract_transfer(
+            Origin::signed(charlie()),
+            1,
+            2
+        ));
+
+        // charlie can no longer pay for the name
+        assert_ok!(Balances::transfer(Origin::signed(charlie()), alice(), 99999));
+        run_to_block(6);
+
+        assert_eq!(
+            SmartContractModule::accept_name_contract_transfer(Origin::signed(bob()), 1),
+            Err(Error::<TestRuntime>::ContractIsCanceled.into())
+        );
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.twin_id, 3);
+        assert_eq!(contract.state, types::ContractState::Deleted(types::Cause::OutOfFunds));
+        assert_eq!(SmartContractModule::name_contract_transfer_offers(1), 0);
+        assert_eq!(
+            SmartContractModule::contract_id_by_name_registration("foobar".as_bytes().to_vec()),
+            0
+        );
+    });
+}
+
+#[test]
+fn test_name_contract_transfer_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+
+        assert_noop!(
+            SmartContractModule::offer_name_contract_transfer(Origin::signed(alice()), 1, 3),
+            Error::<TestRuntime>::TwinNotAuthorizedToUpdateContract
+        );
+        assert_noop!(
+            SmartContractModule::offer_name_contract_transfer(Origin::signed(bob()), 1, 2),
+            Error::<TestRuntime>::NameContractTransferToSameTwin
+        );
+        assert_noop!(
+            SmartContractModule::accept_name_contract_transfer(Origin::signed(charlie()), 1),
+            Error::<TestRuntime>::NameContractTransferNotOffered
+        );
+
+        assert_ok!(SmartContractModule::offer_name_contract_transfer(
+            Origin::signed(bob()),
+            1,
+            3
+        ));
+        assert_noop!(
+            SmartContractModule::accept_name_contract_transfer(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::NameContractTransferNotAuthorized
+        );
+
+        assert_ok!(SmartContractModule::cancel_name_contract_transfer(
+            Origin::signed(bob()),
+            1
+        ));
+        assert_noop!(
+            SmartContractModule::accept_name_contract_transfer(Origin::signed(charlie()), 1),
+            Error::<TestRuntime>::NameContractTransferNotOffered
+        );
+    });
+}
+
+#[test]
+fn test_deploy_node_contract_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+
+        // billing only starts once the node confirmed the deployment
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(11).len(), 0);
+
+        run_to_block(5);
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.state, types::ContractState::Deployed);
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(15), [1]);
+        assert_eq!(SmartContractModule::active_node_contracts(1), [1]);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            our_events.last(),
+            Some(&RawEvent::ContractDeployed(1, alice()))
+        );
+
+        // the contract is no longer waiting to be deployed
+        assert_noop!(
+            SmartContractModule::deploy_contract(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::ContractNotAwaitingDeployment
+        );
+
+        // and is not canceled when the deployment timeout passes
+        run_to_block(22);
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.state, types::ContractState::Deployed);
+    });
+}
+
+#[test]
+fn test_deploy_node_contract_by_other_twin_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+
+        assert_noop!(
+            SmartContractModule::deploy_contract(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::NodeNotAuthorizedToDeployContract
+        );
+        assert_noop!(
+            SmartContractModule::reject_contract(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::NodeNotAuthorizedToDeployContract
+        );
+    });
+}
+
+#[test]
+fn test_reject_node_contract_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+
+        assert_ok!(SmartContractModule::reject_contract(Origin::signed(alice()), 1));
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(
+            contract.state,
+            types::ContractState::Deleted(types::Cause::RejectedByNode)
+        );
+        assert_eq!(SmartContractModule::active_node_contracts(1).len(), 0);
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
+    });
+}
+
+#[test]
+fn test_node_contract_not_deployed_in_time_is_canceled() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
 
         assert_ok!(SmartContractModule::create_node_contract(
             Origin::signed(bob()),
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            1
+            1,
+            Vec::new()
+        ));
+
+        run_to_block(21);
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.state, types::ContractState::Created);
+
+        run_to_block(22);
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(
+            contract.state,
+            types::ContractState::Deleted(types::Cause::DeploymentTimeout)
+        );
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
+
+        assert_noop!(
+            SmartContractModule::deploy_contract(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::ContractNotAwaitingDeployment
+        );
+    });
+}
+
+#[test]
+fn test_cancel_node_contract_by_node_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+
+        push_report(5);
+        run_to_block(5);
+        assert_ok!(SmartContractModule::cancel_contract_by_node(
+            Origin::signed(alice()),
+            1,
+            types::Cause::NodeFailure
+        ));
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(
+            contract.state,
+            types::ContractState::Deleted(types::Cause::NodeFailure)
+        );
+        assert_eq!(SmartContractModule::active_node_contracts(1).len(), 0);
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips[0].contract_id, 0);
+
+        // the usage up to now is billed right away
+        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
+        assert_eq!(contract_billing_info.amount_unbilled, 0);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events
+            .iter()
+            .any(|e| matches!(e, RawEvent::ContractBilled(bill) if bill.contract_id == 1)));
+        assert_eq!(
+            our_events.last(),
+            Some(&RawEvent::NodeContractCanceledByNode(1, 1, 2, types::Cause::NodeFailure))
+        );
+
+        assert_noop!(
+            SmartContractModule::cancel_contract_by_node(
+                Origin::signed(alice()),
+                1,
+                types::Cause::NodeFailure
+            ),
+            Error::<TestRuntime>::ContractIsCanceled
+        );
+    });
+}
+
+#[test]
+fn test_cancel_node_contract_by_node_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+
+        assert_noop!(
+            SmartContractModule::cancel_contract_by_node(
+                Origin::signed(bob()),
+                1,
+                types::Cause::FarmDecommissioned
+            ),
+            Error::<TestRuntime>::NodeNotAuthorizedToCancelContract
+        );
+
+        // a node can only cancel with one of the node causes
+        assert_noop!(
+            SmartContractModule::cancel_contract_by_node(
+                Origin::signed(alice()),
+                1,
+                types::Cause::OutOfFunds
+            ),
+            Error::<TestRuntime>::InvalidCancelCause
+        );
+    });
+}
+
+#[test]
+fn test_cancel_node_contract_bills_ip_usage_since_last_bill() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(11), [1]);
+
+        run_to_block(6);
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(bob()), 1));
+
+        // the ip was used for 5 blocks and is billed right away
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        match our_events.last() {
+            Some(RawEvent::ContractBilled(bill)) => {
+                assert_eq!(bill.contract_id, 1);
+                assert_eq!(bill.timestamp, 1628082000 + 6 * 6);
+                assert!(bill.amount_billed > 0);
+            }
+            e => panic!("expected a contract bill, got {:?}", e),
+        }
+
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(11).len(), 0);
+        assert_eq!(SmartContractModule::contract_next_billing_block(1), 0);
+    });
+}
+
+#[test]
+fn test_contract_billing_loop() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 1).unwrap();
+        run_to_block(1);
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            1,
+            Vec::new()
         ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
 
         let contract_to_bill_at_block = SmartContractModule::contract_to_bill_at_block(11);
         assert_eq!(contract_to_bill_at_block.len(), 1);
@@ -541,10 +1743,9 @@ fn test_contract_billing_loop() {
             2
         ));
 
-        // after a canceling the second contract it should still be in the contract to be billed map
-        // but it should be removed from the next billing cycle since it's canceled and it does not have unbilled amounts
+        // after canceling the second contract it is settled and removed from the billing cycle right away
         let contract_to_bill_at_block = SmartContractModule::contract_to_bill_at_block(61);
-        assert_eq!(contract_to_bill_at_block.len(), 2);
+        assert_eq!(contract_to_bill_at_block.len(), 1);
 
         run_to_block(62);
         let contract_to_bill_at_block = SmartContractModule::contract_to_bill_at_block(71);
@@ -564,8 +1765,10 @@ fn test_node_contract_billing() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            1
+            1,
+            Vec::new()
         ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
 
         let contract_to_bill = SmartContractModule::contract_to_bill_at_block(11);
         assert_eq!(contract_to_bill, [1]);
@@ -574,7 +1777,7 @@ fn test_node_contract_billing() {
 
         push_report(11);
         run_to_block(12);
-        check_report_cost(3, 36070, 12, types::DiscountLevel::Gold);
+        check_report_cost(5, 36070, 12, types::DiscountLevel::Gold);
 
         // check the contract owners address to see if it got balance credited
         let twin = TfgridModule::twins(2);
@@ -604,51 +1807,210 @@ fn test_node_contract_billing() {
         // total issueance is now previous total - amount burned from contract billed (35%)
         assert_eq!(total_issuance, initial_total_issuance - 12625);
 
-        // amount unbilled should have been reset after a transfer between contract owner and farmer
-        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
-        assert_eq!(contract_billing_info.amount_unbilled, 0);
+        // amount unbilled should have been reset after a transfer between contract owner and farmer
+        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
+        assert_eq!(contract_billing_info.amount_unbilled, 0);
+    });
+}
+
+#[test]
+fn test_node_contract_billing_cycles() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(bob()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+
+        push_report(11);
+        run_to_block(12);
+        check_report_cost(4, 25895, 12, types::DiscountLevel::Gold);
+
+        push_report(21);
+        run_to_block(22);
+        check_report_cost(7, 25559, 22, types::DiscountLevel::Gold);
+
+        push_report(31);
+        run_to_block(32);
+        check_report_cost(10, 25559, 32, types::DiscountLevel::Gold);
+
+        push_report(41);
+        run_to_block(42);
+        check_report_cost(13, 25559, 42, types::DiscountLevel::Gold);
+
+        push_report(51);
+        run_to_block(52);
+        check_report_cost(16, 25559, 52, types::DiscountLevel::Gold);
+    });
+}
+
+#[test]
+fn test_node_contract_billing_should_cancel_contract_when_out_of_funds() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(charlie()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
+
+        push_report(11);
+        run_to_block(12);
+        check_report_cost(4, 64736, 12, types::DiscountLevel::None);
+
+        let twin = TfgridModule::twins(3);
+        let b = Balances::free_balance(&twin.account_id);
+        let balances_as_u128: u128 = b.saturated_into::<u128>();
+
+        let twin2_balance_should_be = 100000 - 64736 as u128;
+        assert_eq!(balances_as_u128, twin2_balance_should_be);
+
+        // the twin can't pay the next cycle, the contract is kept during the grace period
+        push_report(21);
+        run_to_block(22);
+
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::GracePeriod(21));
+        let b = Balances::free_balance(&twin.account_id);
+        assert_eq!(b.saturated_into::<u128>(), twin2_balance_should_be);
+
+        // no resources can be added while the contract is not paid for
+        assert_noop!(
+            SmartContractModule::update_node_contract(
+                Origin::signed(charlie()),
+                1,
+                "some_data".as_bytes().to_vec(),
+                "hash".as_bytes().to_vec(),
+                Some(1),
+                Vec::new()
+            ),
+            Error::<TestRuntime>::ContractInGracePeriod
+        );
+
+        push_report(31);
+        run_to_block(32);
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::GracePeriod(21));
+
+        // when the grace period ended the twin is drained and the contract is canceled
+        push_report(41);
+        run_to_block(42);
+        check_report_cost(10, 35264, 42, types::DiscountLevel::None);
+
+        let twin = TfgridModule::twins(3);
+        let b = Balances::free_balance(&twin.account_id);
+        assert_eq!(b, 1);
+
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::Deleted(types::Cause::OutOfFunds));
+
+        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
+        assert_eq!(contract_billing_info.amount_unbilled, 0); //this amount in unit USD = 1/1e7
+
+        let our_events = System::events()
+        .into_iter()
+        .map(|r| r.event)
+        .filter_map(|e| {
+            if let Event::pallet_smart_contract(inner) = e {
+                Some(inner)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+        
+        let mut expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> = Vec::new();
+        expected_events.push(RawEvent::ContractGracePeriodStarted(1, 1, 3, 21));
+        expected_events.push(RawEvent::NodeContractCanceled(1, 1, 3));
+
+        assert_eq!(our_events[6], expected_events[0]);
+        assert_eq!(our_events[12], expected_events[1]);
+
+        // what could not be paid is kept as debt of the twin
+        let debt = SmartContractModule::contract_debt(3, 1);
+        assert!(debt > 0);
+        assert_eq!(SmartContractModule::twin_debt(3), debt);
+        assert_eq!(our_events[11], RawEvent::DebtIncurred(1, 3, debt));
     });
 }
 
 #[test]
-fn test_node_contract_billing_cycles() {
+fn test_node_contract_grace_period_ends_when_twin_is_funded() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
         run_to_block(1);
         TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
 
         assert_ok!(SmartContractModule::create_node_contract(
-            Origin::signed(bob()),
+            Origin::signed(charlie()),
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
 
         push_report(11);
         run_to_block(12);
-        check_report_cost(3, 25895, 12, types::DiscountLevel::Gold);
-
         push_report(21);
         run_to_block(22);
-        check_report_cost(6, 25559, 22, types::DiscountLevel::Gold);
 
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::GracePeriod(21));
+        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
+        assert!(contract_billing_info.amount_unbilled > 0);
+
+        // top up the twin, the accrued cost is billed and the contract is restored
+        assert_ok!(Balances::transfer(Origin::signed(bob()), charlie(), 1000000));
         push_report(31);
         run_to_block(32);
-        check_report_cost(9, 25559, 32, types::DiscountLevel::Gold);
 
-        push_report(41);
-        run_to_block(42);
-        check_report_cost(12, 25559, 42, types::DiscountLevel::Gold);
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::Deployed);
+        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
+        assert_eq!(contract_billing_info.amount_unbilled, 0);
 
-        push_report(51);
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            our_events.last(),
+            Some(&RawEvent::ContractGracePeriodEnded(1, 1, 3))
+        );
+
+        // the grace period has ended, so the contract is not decomissioned afterwards
         run_to_block(52);
-        check_report_cost(15, 25559, 52, types::DiscountLevel::Gold);
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::Deployed);
     });
 }
 
 #[test]
-fn test_node_contract_billing_should_cancel_contract_when_out_of_funds() {
+fn test_twin_debt_is_settled_before_creating_contracts() {
     new_test_ext().execute_with(|| {
         prepare_farm_and_node();
         run_to_block(1);
@@ -659,50 +2021,84 @@ fn test_node_contract_billing_should_cancel_contract_when_out_of_funds() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            0
+            0,
+            Vec::new()
         ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
 
-        push_report(11);
-        run_to_block(12);
-        check_report_cost(3, 64736, 12, types::DiscountLevel::None);
+        // run out of funds until the grace period ended
+        for block in &[11, 21, 31, 41] {
+            push_report(*block);
+            run_to_block(block + 1);
+        }
+        let c1 = SmartContractModule::contracts(1);
+        assert_eq!(c1.state, types::ContractState::Deleted(types::Cause::OutOfFunds));
+        let debt = SmartContractModule::twin_debt(3);
+        assert!(debt > 0);
 
-        let twin = TfgridModule::twins(3);
-        let b = Balances::free_balance(&twin.account_id);
-        let balances_as_u128: u128 = b.saturated_into::<u128>();
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(charlie()),
+                "foobar".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::TwinHasDebt
+        );
 
-        let twin2_balance_should_be = 100000 - 64736 as u128;
-        assert_eq!(balances_as_u128, twin2_balance_should_be);
+        // once the twin is funded the debt is paid off first
+        assert_ok!(Balances::transfer(Origin::signed(bob()), charlie(), 1000000));
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(charlie()),
+            "foobar".as_bytes().to_vec()
+        ));
 
-        push_report(21);
-        run_to_block(22);
-        check_report_cost(6, 35264, 22, types::DiscountLevel::None);
+        assert_eq!(SmartContractModule::twin_debt(3), 0);
+        assert_eq!(SmartContractModule::contract_debt(3, 1), 0);
+        let b = Balances::free_balance(charlie());
+        assert_eq!(b, 1 + 1000000 - debt);
 
-        let twin = TfgridModule::twins(3);
-        let b = Balances::free_balance(&twin.account_id);
-        assert_eq!(b, 1);
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events.contains(&RawEvent::DebtSettled(1, 3, debt)));
+    });
+}
 
-        let c1 = SmartContractModule::contracts(1);
-        assert_eq!(c1.state, types::ContractState::Deleted(types::Cause::OutOfFunds));
+#[test]
+fn test_twin_debt_is_settled_after_node_is_deleted() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
 
-        let contract_billing_info = SmartContractModule::contract_billing_information_by_id(1);
-        assert_eq!(contract_billing_info.amount_unbilled, 0); //this amount in unit USD = 1/1e7
+        assert_ok!(SmartContractModule::create_node_contract(
+            Origin::signed(charlie()),
+            1,
+            "some_data".as_bytes().to_vec(),
+            "hash".as_bytes().to_vec(),
+            0,
+            Vec::new()
+        ));
+        crate::ContractDebt::<TestRuntime>::insert(3, 1, 100);
+        crate::TwinDebt::<TestRuntime>::insert(3, 100);
 
-        let our_events = System::events()
-        .into_iter()
-        .map(|r| r.event)
-        .filter_map(|e| {
-            if let Event::pallet_smart_contract(inner) = e {
-                Some(inner)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
-        
-        let mut expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> = Vec::new();
-        expected_events.push(RawEvent::NodeContractCanceled(1, 1, 3));
+        assert_ok!(TfgridModule::delete_node(Origin::signed(alice()), 1));
+
+        assert_ok!(Balances::transfer(Origin::signed(bob()), charlie(), 1000000));
+        assert_ok!(SmartContractModule::settle_debt(Origin::signed(charlie())));
+        assert_eq!(SmartContractModule::twin_debt(3), 0);
+        assert_eq!(SmartContractModule::contract_debt(3, 1), 0);
 
-        assert_eq!(our_events[7], expected_events[0]);
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(charlie()),
+            "foobar".as_bytes().to_vec()
+        ));
     });
 }
 
@@ -718,8 +2114,10 @@ fn test_new_contract_bill() {
             1,
             "some_data".as_bytes().to_vec(),
             "hash".as_bytes().to_vec(),
-            1
+            1,
+            Vec::new()
         ));
+        assert_ok!(SmartContractModule::deploy_contract(Origin::signed(alice()), 1));
 
         let gigabyte = 1000 * 1000 * 1000;
         let mut consumption_reports = Vec::new();
@@ -762,7 +2160,7 @@ fn test_new_contract_bill() {
         let mut expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> = Vec::new();
         expected_events.push(RawEvent::ContractBilled(contract_bill_event));
 
-        assert_eq!(our_events[3], expected_events[0]);
+        assert_eq!(our_events[5], expected_events[0]);
     })
 }
 
@@ -785,30 +2183,300 @@ fn push_report(block_number: u64) {
     ));
 }
 
-fn check_report_cost(index: usize, amount_billed: u128, block_number: u64, discount_level: types::DiscountLevel) {
-    // Test that the expected events were emitted
-    let our_events = System::events()
-    .into_iter()
-    .map(|r| r.event)
-    .filter_map(|e| {
-        if let Event::pallet_smart_contract(inner) = e {
-            Some(inner)
-        } else {
-            None
-        }
-    })
-    .collect::<Vec<_>>();
+fn check_report_cost(index: usize, amount_billed: u128, block_number: u64, discount_level: types::DiscountLevel) {
+    // Test that the expected events were emitted
+    let our_events = System::events()
+    .into_iter()
+    .map(|r| r.event)
+    .filter_map(|e| {
+        if let Event::pallet_smart_contract(inner) = e {
+            Some(inner)
+        } else {
+            None
+        }
+    })
+    .collect::<Vec<_>>();
+
+    let contract_bill_event = types::ContractBill {
+        contract_id: 1,
+        timestamp: 1628082000 + (6*block_number),
+        discount_level,
+        amount_billed
+    };
+    let mut expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> = Vec::new();
+    expected_events.push(RawEvent::ContractBilled(contract_bill_event));
+
+    assert_eq!(our_events[index], expected_events[0]);
+}
+
+#[test]
+fn test_create_domain_contract_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        prepare_gateway();
+
+        assert_ok!(SmartContractModule::create_domain_contract(
+            Origin::signed(bob()),
+            1,
+            "www.example.com".as_bytes().to_vec()
+        ));
+
+        let contract = SmartContractModule::contracts(1);
+        let domain_contract = match contract.contract_type {
+            types::ContractData::DomainContract(c) => c,
+            _ => panic!("expected a domain contract"),
+        };
+        assert_eq!(domain_contract.domain, "www.example.com".as_bytes().to_vec());
+        assert_eq!(domain_contract.gateway_id, 1);
+        assert_eq!(domain_contract.challenge.len(), 64);
+        assert_eq!(
+            SmartContractModule::contract_id_by_domain("www.example.com".as_bytes().to_vec()),
+            1
+        );
+
+        // billing starts once the gateway verified the domain
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(10).len(), 0);
+
+        assert_noop!(
+            SmartContractModule::create_domain_contract(
+                Origin::signed(alice()),
+                1,
+                "www.example.com".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::DomainExists
+        );
+        // domains are case insensitive
+        assert_noop!(
+            SmartContractModule::create_domain_contract(
+                Origin::signed(alice()),
+                1,
+                "WWW.Example.com".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::DomainExists
+        );
+        assert_ok!(SmartContractModule::create_domain_contract(
+            Origin::signed(alice()),
+            1,
+            "Example.com".as_bytes().to_vec()
+        ));
+        let contract = SmartContractModule::contracts(2);
+        let domain_contract = SmartContractModule::get_domain_contract(&contract).unwrap();
+        assert_eq!(domain_contract.domain, "example.com".as_bytes().to_vec());
+        assert_eq!(
+            SmartContractModule::contract_id_by_domain("example.com".as_bytes().to_vec()),
+            2
+        );
+        assert_noop!(
+            SmartContractModule::create_domain_contract(
+                Origin::signed(alice()),
+                2,
+                "example.org".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::GatewayNotExists
+        );
+    });
+}
+
+#[test]
+fn test_create_domain_contract_invalid_domain_fails() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        prepare_gateway();
+
+        for domain in &[
+            "example",
+            "-example.com",
+            "example-.com",
+            "example..com",
+            "example.com.",
+            "example.123",
+            "exa_mple.com",
+        ] {
+            assert_noop!(
+                SmartContractModule::create_domain_contract(
+                    Origin::signed(bob()),
+                    1,
+                    domain.as_bytes().to_vec()
+                ),
+                Error::<TestRuntime>::DomainNotValid
+            );
+        }
+    });
+}
+
+#[test]
+fn test_verify_domain_contract_works() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        prepare_gateway();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_domain_contract(
+            Origin::signed(bob()),
+            1,
+            "example.com".as_bytes().to_vec()
+        ));
+
+        assert_noop!(
+            SmartContractModule::verify_domain_contract(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::GatewayNotAuthorizedToVerifyDomain
+        );
+        assert_ok!(SmartContractModule::verify_domain_contract(Origin::signed(eve()), 1));
+
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(contract.state, types::ContractState::Deployed);
+        assert_eq!(SmartContractModule::contract_to_bill_at_block(11), [1]);
+
+        // the domain is billed with the domain name price of the gateway's farm
+        run_to_block(12);
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events
+            .iter()
+            .any(|e| matches!(e, RawEvent::ContractBilled(bill) if bill.contract_id == 1 && bill.amount_billed > 0)));
+
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(bob()), 1));
+        assert_eq!(
+            SmartContractModule::contract_id_by_domain("example.com".as_bytes().to_vec()),
+            0
+        );
+        let our_events = System::events();
+        assert!(our_events.iter().any(|r| r.event
+            == Event::pallet_smart_contract(RawEvent::DomainContractCanceled(1))));
+    });
+}
+
+#[test]
+fn test_domain_contract_is_canceled_when_its_gateway_is_removed() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        prepare_gateway();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_domain_contract(
+            Origin::signed(bob()),
+            1,
+            "example.com".as_bytes().to_vec()
+        ));
+        assert_ok!(SmartContractModule::verify_domain_contract(Origin::signed(eve()), 1));
+
+        assert_ok!(TfgridModule::delete_gateway(Origin::signed(alice()), 1));
+
+        // the next cycle cancels the contract and frees the domain
+        run_to_block(12);
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(
+            contract.state,
+            types::ContractState::Deleted(types::Cause::GatewayRemoved)
+        );
+        assert_eq!(
+            SmartContractModule::contract_id_by_domain("example.com".as_bytes().to_vec()),
+            0
+        );
+        let our_events = System::events();
+        assert!(our_events.iter().any(|r| r.event
+            == Event::pallet_smart_contract(RawEvent::DomainContractCanceled(1))));
+        assert!(our_events.iter().any(|r| matches!(
+            &r.event,
+            Event::pallet_smart_contract(RawEvent::ContractBilled(bill)) if bill.contract_id == 1 && bill.amount_billed > 0
+        )));
+
+        run_to_block(22);
+        assert!(!SmartContractModule::contract_to_bill_at_block(21).contains(&1));
+    });
+}
+
+#[test]
+fn test_name_contract_pricing_policy_is_set_by_governance() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        assert_noop!(
+            SmartContractModule::set_name_contract_pricing_policy(Origin::signed(alice()), 1),
+            BadOrigin
+        );
+        assert_noop!(
+            SmartContractModule::set_name_contract_pricing_policy(RawOrigin::Root.into(), 2),
+            Error::<TestRuntime>::PricingPolicyNotExists
+        );
+        assert_ok!(SmartContractModule::set_name_contract_pricing_policy(
+            RawOrigin::Root.into(),
+            1
+        ));
+        let our_events = System::events();
+        assert_eq!(
+            our_events.last().unwrap().event,
+            Event::pallet_smart_contract(RawEvent::NameContractPricingPolicySet(1))
+        );
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(alice()),
+            "foobar".as_bytes().to_vec()
+        ));
+        let contract = SmartContractModule::contracts(1);
+        assert_eq!(
+            contract.contract_type,
+            types::ContractData::NameContract(types::NameContract {
+                name: "foobar".as_bytes().to_vec(),
+                pricing_policy_id: 1,
+            })
+        );
+
+        assert_noop!(
+            SmartContractModule::update_name_contract_pricing_policy(Origin::signed(alice()), 1, 1),
+            BadOrigin
+        );
+        assert_noop!(
+            SmartContractModule::update_name_contract_pricing_policy(RawOrigin::Root.into(), 1, 2),
+            Error::<TestRuntime>::PricingPolicyNotExists
+        );
+        assert_ok!(SmartContractModule::update_name_contract_pricing_policy(
+            RawOrigin::Root.into(),
+            1,
+            1
+        ));
+    });
+}
+
+#[test]
+fn test_update_name_contract_pricing_policy_bills_at_the_old_policy() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
 
-    let contract_bill_event = types::ContractBill {
-        contract_id: 1,
-        timestamp: 1628082000 + (6*block_number),
-        discount_level,
-        amount_billed
-    };
-    let mut expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> = Vec::new();
-    expected_events.push(RawEvent::ContractBilled(contract_bill_event));
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(alice()),
+            "foobar".as_bytes().to_vec()
+        ));
+        run_to_block(6);
 
-    assert_eq!(our_events[index], expected_events[0]);
+        // the time since the last bill is accrued before the pricing policy changes
+        assert_ok!(SmartContractModule::update_name_contract_pricing_policy(
+            RawOrigin::Root.into(),
+            1,
+            1
+        ));
+        assert_ne!(SmartContractModule::contract_billing_information_by_id(1).amount_unbilled, 0);
+        assert_eq!(SmartContractModule::contract_billed_at(1), 1628082000 + 6 * 6);
+
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(alice()), 1));
+        assert_noop!(
+            SmartContractModule::update_name_contract_pricing_policy(RawOrigin::Root.into(), 1, 1),
+            Error::<TestRuntime>::ContractIsCanceled
+        );
+    });
 }
 
 #[test]
@@ -843,15 +2511,497 @@ fn test_name_contract_billing() {
             })
             .collect::<Vec<_>>();
 
+        // 66 seconds of the unique name price, with the gold discount bob's balance gives
         let contract_bill_event = types::ContractBill {
             contract_id: 1,
             timestamp: 1628082072,
-            discount_level: types::DiscountLevel::None,
-            amount_billed: 277983,
+            discount_level: types::DiscountLevel::Gold,
+            amount_billed: 2032,
         };
         let expected_events: std::vec::Vec<RawEvent<AccountId, BalanceOf<TestRuntime>>> =
             vec![RawEvent::ContractBilled(contract_bill_event)];
-        assert_eq!(our_events[1], expected_events[0]);
+        assert_eq!(our_events[2], expected_events[0]);
+    });
+}
+
+#[test]
+fn test_name_contract_billing_settles_debt_first() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+        let twin_id = SmartContractModule::contracts(1).twin_id;
+
+        // debt left over from an earlier bill of the contract
+        crate::ContractDebt::<TestRuntime>::insert(twin_id, 1, 100);
+        crate::TwinDebt::<TestRuntime>::insert(twin_id, 100);
+
+        run_to_block(12);
+
+        assert_eq!(SmartContractModule::twin_debt(twin_id), 0);
+        assert_eq!(SmartContractModule::contract_debt(twin_id, 1), 0);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let settled = our_events
+            .iter()
+            .position(|e| *e == RawEvent::DebtSettled(1, twin_id, 100))
+            .unwrap();
+        let billed = our_events
+            .iter()
+            .position(|e| matches!(e, RawEvent::ContractBilled(_)))
+            .unwrap();
+        assert!(settled < billed);
+    });
+}
+
+#[test]
+fn test_set_revenue_distribution_fails() {
+    new_test_ext().execute_with(|| {
+        let share = |destination, percent| types::DistributionShare {
+            destination,
+            share: Perbill::from_percent(percent),
+        };
+
+        assert_noop!(
+            SmartContractModule::set_revenue_distribution(
+                Origin::signed(alice()),
+                vec![share(types::DistributionDestination::Burn, 100)]
+            ),
+            BadOrigin
+        );
+        assert_noop!(
+            SmartContractModule::set_revenue_distribution(
+                RawOrigin::Root.into(),
+                vec![
+                    share(types::DistributionDestination::Foundation, 50),
+                    share(types::DistributionDestination::Burn, 40),
+                ]
+            ),
+            Error::<TestRuntime>::RevenueDistributionNotComplete
+        );
+        assert_noop!(
+            SmartContractModule::set_revenue_distribution(
+                RawOrigin::Root.into(),
+                vec![
+                    share(types::DistributionDestination::Foundation, 50),
+                    share(types::DistributionDestination::Foundation, 50),
+                ]
+            ),
+            Error::<TestRuntime>::RevenueDistributionDuplicateDestination
+        );
+        assert_eq!(
+            SmartContractModule::revenue_distribution(),
+            types::default_revenue_distribution()
+        );
+    });
+}
+
+#[test]
+fn test_revenue_distribution_accounts_for_billed_amount() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+        TFTPriceModule::set_prices(Origin::signed(bob()), U16F16::from_num(0.05), 101).unwrap();
+
+        let share = |destination, percent| types::DistributionShare {
+            destination,
+            share: Perbill::from_percent(percent),
+        };
+        // shares that do not divide the bill evenly, whatever is not transferred is burned
+        let distribution = vec![
+            share(types::DistributionDestination::Foundation, 33),
+            share(types::DistributionDestination::StakingPool, 33),
+            share(types::DistributionDestination::SalesChannel, 33),
+            share(types::DistributionDestination::Burn, 1),
+        ];
+        assert_ok!(SmartContractModule::set_revenue_distribution(
+            RawOrigin::Root.into(),
+            distribution.clone()
+        ));
+        assert_eq!(SmartContractModule::revenue_distribution(), distribution);
+
+        let foundation_balance = Balances::free_balance(ferdie());
+        let staking_pool_balance = Balances::free_balance(get_staking_pool_account());
+        let sales_balance = Balances::free_balance(eve());
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+        run_to_block(12);
+
+        let our_events = System::events()
+            .into_iter()
+            .map(|r| r.event)
+            .filter_map(|e| {
+                if let Event::pallet_smart_contract(inner) = e {
+                    Some(inner)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(our_events.contains(&RawEvent::RevenueDistributionSet(distribution)));
+
+        let mut amount_billed: u64 = 0;
+        let mut amount_burned = 0;
+        for event in our_events {
+            match event {
+                RawEvent::ContractBilled(contract_bill) => {
+                    amount_billed += contract_bill.amount_billed.saturated_into::<u64>()
+                }
+                RawEvent::TokensBurned(_, amount) => amount_burned += amount,
+                _ => (),
+            }
+        }
+        assert_ne!(amount_billed, 0);
+
+        let amount_transferred = (Balances::free_balance(ferdie()) - foundation_balance)
+            + (Balances::free_balance(get_staking_pool_account()) - staking_pool_balance)
+            + (Balances::free_balance(eve()) - sales_balance);
+        assert_eq!(amount_transferred + amount_burned, amount_billed);
+    });
+}
+
+#[test]
+fn test_revenue_distribution_of_small_amounts() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        run_to_block(1);
+
+        let share = |destination, percent| types::DistributionShare {
+            destination,
+            share: Perbill::from_percent(percent),
+        };
+        // rounded to the nearest unit these shares add up to more than the amount
+        assert_ok!(SmartContractModule::set_revenue_distribution(
+            RawOrigin::Root.into(),
+            vec![
+                share(types::DistributionDestination::Foundation, 40),
+                share(types::DistributionDestination::StakingPool, 30),
+                share(types::DistributionDestination::SalesChannel, 30),
+            ]
+        ));
+
+        assert_ok!(SmartContractModule::create_name_contract(
+            Origin::signed(bob()),
+            "foobar".as_bytes().to_vec()
+        ));
+        let contract = SmartContractModule::contracts(1);
+        let pricing_policy = pallet_tfgrid::PricingPolicies::<TestRuntime>::get(1);
+
+        for amount in 1..=3 {
+            let twin_balance = Balances::free_balance(bob());
+            let transferred_balance = Balances::free_balance(ferdie())
+                + Balances::free_balance(get_staking_pool_account())
+                + Balances::free_balance(eve());
+
+            assert_ok!(SmartContractModule::_distribute_cultivation_rewards(
+                &contract,
+                &pricing_policy,
+                amount
+            ));
+
+            let amount_transferred = Balances::free_balance(ferdie())
+                + Balances::free_balance(get_staking_pool_account())
+                + Balances::free_balance(eve())
+                - transferred_balance;
+            assert!(amount_transferred <= amount);
+            assert_eq!(twin_balance - Balances::free_balance(bob()), amount);
+            assert_eq!(
+                System::events().last().unwrap().event,
+                Event::pallet_smart_contract(RawEvent::TokensBurned(1, amount - amount_transferred))
+            );
+        }
+    });
+}
+
+#[test]
+fn test_set_discount_configuration_works() {
+    new_test_ext().execute_with(|| {
+        let tier = |level, months, percent| types::DiscountTier {
+            level,
+            months,
+            multiplier: Perbill::from_percent(percent),
+        };
+
+        // with the default tiers a balance covering 36 months gets the gold discount
+        assert_eq!(
+            SmartContractModule::_calculate_discount(
+                1000,
+                100000000,
+                pallet_tfgrid_types::CertificationType::Diy
+            ),
+            (400, types::DiscountLevel::Gold)
+        );
+
+        assert_noop!(
+            SmartContractModule::set_discount_configuration(
+                Origin::signed(alice()),
+                vec![tier(types::DiscountLevel::Gold, 1, 50)],
+                Perbill::zero()
+            ),
+            BadOrigin
+        );
+        assert_noop!(
+            SmartContractModule::set_discount_configuration(
+                RawOrigin::Root.into(),
+                vec![
+                    tier(types::DiscountLevel::Bronze, 6, 70),
+                    tier(types::DiscountLevel::Silver, 6, 60),
+                ],
+                Perbill::zero()
+            ),
+            Error::<TestRuntime>::DiscountTiersNotAscending
+        );
+        assert_noop!(
+            SmartContractModule::set_discount_configuration(
+                RawOrigin::Root.into(),
+                vec![tier(types::DiscountLevel::None, 1, 50)],
+                Perbill::zero()
+            ),
+            Error::<TestRuntime>::DiscountTierLevelNotValid
+        );
+
+        let tiers = vec![
+            tier(types::DiscountLevel::Bronze, 1, 90),
+            tier(types::DiscountLevel::Gold, 2, 50),
+        ];
+        assert_ok!(SmartContractModule::set_discount_configuration(
+            RawOrigin::Root.into(),
+            tiers.clone(),
+            Perbill::from_percent(10)
+        ));
+        let discount_configuration = types::DiscountConfiguration {
+            tiers,
+            certified_surcharge: Perbill::from_percent(10),
+        };
+        assert_eq!(
+            SmartContractModule::discount_configuration(),
+            discount_configuration
+        );
+
+        // 1000 per hour is 720000 per month
+        assert_eq!(
+            SmartContractModule::_calculate_discount(
+                1000,
+                720000,
+                pallet_tfgrid_types::CertificationType::Diy
+            ),
+            (900, types::DiscountLevel::Bronze)
+        );
+        assert_eq!(
+            SmartContractModule::_calculate_discount(
+                1000,
+                1440000,
+                pallet_tfgrid_types::CertificationType::Certified
+            ),
+            (550, types::DiscountLevel::Gold)
+        );
+        assert_eq!(
+            SmartContractModule::_calculate_discount(
+                1000,
+                700000,
+                pallet_tfgrid_types::CertificationType::Diy
+            ),
+            (1000, types::DiscountLevel::None)
+        );
+    });
+}
+
+#[test]
+fn test_migration_seeds_discount_configuration() {
+    new_test_ext().execute_with(|| {
+        crate::PalletVersion::put(types::PalletStorageVersion::V3);
+        assert!(!crate::DiscountConfiguration::exists());
+
+        SmartContractModule::migrate_storage();
+
+        assert!(crate::DiscountConfiguration::exists());
+        assert_eq!(
+            SmartContractModule::discount_configuration(),
+            types::default_discount_configuration()
+        );
+    });
+}
+
+#[test]
+fn test_migration_translates_original_contracts() {
+    new_test_ext().execute_with(|| {
+        // V3 is the last version holding the original contract layout
+        crate::PalletVersion::put(types::PalletStorageVersion::V3);
+
+        let old_node_contract = types::deprecated::ContractV1 {
+            version: 1,
+            state: types::ContractState::Created,
+            contract_id: 1,
+            twin_id: 1,
+            contract_type: types::deprecated::ContractDataV1::NodeContract(
+                types::deprecated::NodeContractV1 {
+                    node_id: 1,
+                    deployment_data: "some_data".as_bytes().to_vec(),
+                    deployment_hash: "hash".as_bytes().to_vec(),
+                    public_ips: 1,
+                    public_ips_list: vec![pallet_tfgrid_types::PublicIP {
+                        ip: "185.206.122.33/24".as_bytes().to_vec(),
+                        gateway: "185.206.122.1".as_bytes().to_vec(),
+                        contract_id: 1,
+                    }],
+                },
+            ),
+        };
+        let old_name_contract = types::deprecated::ContractV1 {
+            version: 1,
+            state: types::ContractState::Deleted(types::Cause::CanceledByUser),
+            contract_id: 2,
+            twin_id: 2,
+            contract_type: types::deprecated::ContractDataV1::NameContract(
+                types::deprecated::NameContractV1 {
+                    name: "foobar".as_bytes().to_vec(),
+                },
+            ),
+        };
+        unhashed::put(&crate::Contracts::hashed_key_for(1), &old_node_contract);
+        unhashed::put(&crate::Contracts::hashed_key_for(2), &old_name_contract);
+
+        SmartContractModule::migrate_storage();
+
+        let node_contract = SmartContractModule::contracts(1);
+        assert_eq!(node_contract.version, crate::CONTRACT_VERSION);
+        assert_eq!(node_contract.state, types::ContractState::Deployed);
+        assert_eq!(
+            node_contract.contract_type,
+            types::ContractData::NodeContract(types::NodeContract {
+                node_id: 1,
+                deployment_data: "some_data".as_bytes().to_vec(),
+                deployment_hash: "hash".as_bytes().to_vec(),
+                public_ips: 1,
+                public_ips_list: vec![pallet_tfgrid_types::PublicIP {
+                    ip: "185.206.122.33/24".as_bytes().to_vec(),
+                    gateway: "185.206.122.1".as_bytes().to_vec(),
+                    contract_id: 1,
+                }],
+                public_ipv6_prefix_length: 0,
+                public_ipv6_list: Vec::new(),
+            })
+        );
+
+        let name_contract = SmartContractModule::contracts(2);
+        assert_eq!(name_contract.state, types::ContractState::Deleted(types::Cause::CanceledByUser));
+        assert_eq!(
+            name_contract.contract_type,
+            types::ContractData::NameContract(types::NameContract {
+                name: "foobar".as_bytes().to_vec(),
+                pricing_policy_id: 1,
+            })
+        );
+    });
+}
+
+#[test]
+fn test_migration_lowercases_registered_names() {
+    new_test_ext().execute_with(|| {
+        prepare_farm_and_node();
+        crate::PalletVersion::put(types::PalletStorageVersion::V3);
+
+        for (id, name) in &[(1, "FooBar"), (2, "FOOBAR"), (3, "Other"), (4, "other"), (5, "b_a_z")] {
+            let old_contract = types::deprecated::ContractV1 {
+                version: 1,
+                state: types::ContractState::Created,
+                contract_id: *id,
+                twin_id: 2,
+                contract_type: types::deprecated::ContractDataV1::NameContract(
+                    types::deprecated::NameContractV1 {
+                        name: name.as_bytes().to_vec(),
+                    },
+                ),
+            };
+            unhashed::put(&crate::Contracts::hashed_key_for(id), &old_contract);
+            crate::ContractIDByNameRegistration::insert(name.as_bytes().to_vec(), id);
+        }
+        crate::ContractID::put(5);
+
+        SmartContractModule::migrate_storage();
+
+        let registration = |name: &str| SmartContractModule::contract_id_by_name_registration(name.as_bytes().to_vec());
+        let name_of = |id| match SmartContractModule::contracts(id).contract_type {
+            types::ContractData::NameContract(name_contract) => name_contract.name,
+            _ => panic!("expected a name contract"),
+        };
+
+        // the oldest contract gets the lowercase name
+        assert_eq!(registration("foobar"), 1);
+        assert_eq!(registration("FooBar"), 0);
+        assert_eq!(name_of(1), "foobar".as_bytes().to_vec());
+        assert_eq!(registration("FOOBAR"), 2);
+        assert_eq!(name_of(2), "FOOBAR".as_bytes().to_vec());
+
+        // a name registered in lowercase keeps it
+        assert_eq!(registration("other"), 4);
+        assert_eq!(registration("Other"), 3);
+
+        // names that do not follow the rules are kept
+        assert_eq!(registration("b_a_z"), 5);
+
+        assert_noop!(
+            SmartContractModule::create_name_contract(
+                Origin::signed(bob()),
+                "FooBar".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::NameExists
+        );
+
+        // cancelling releases the lowercase name
+        assert_ok!(SmartContractModule::cancel_contract(Origin::signed(bob()), 1));
+        assert_eq!(registration("foobar"), 0);
+    });
+}
+
+#[test]
+fn test_migration_on_new_chain_does_nothing() {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<TestRuntime>()
+        .unwrap();
+    crate::GenesisConfig::default()
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+    sp_io::TestExternalities::from(storage).execute_with(|| {
+        let contract = types::Contract {
+            version: crate::CONTRACT_VERSION,
+            state: types::ContractState::Deployed,
+            contract_id: 1,
+            twin_id: 1,
+            contract_type: types::ContractData::NodeContract(types::NodeContract {
+                node_id: 1,
+                public_ipv6_prefix_length: 64,
+                public_ipv6_list: vec![pallet_tfgrid_types::PublicIPv6Allocation {
+                    prefix: "2a02:1802:5e:1::/64".as_bytes().to_vec(),
+                    gateway: "2a02:1802:5e::1".as_bytes().to_vec(),
+                    contract_id: 1,
+                }],
+                ..Default::default()
+            }),
+        };
+        crate::Contracts::insert(1, &contract);
+
+        assert_eq!(SmartContractModule::migrate_storage(), 0);
+        assert_eq!(SmartContractModule::contracts(1), contract);
     });
 }
 
@@ -886,8 +3036,8 @@ fn prepare_farm_and_node() {
     let farm_name = "test_farm";
     let mut pub_ips = Vec::new();
     pub_ips.push(pallet_tfgrid_types::PublicIP {
-        ip: "1.1.1.0".as_bytes().to_vec(),
-        gateway: "1.1.1.1".as_bytes().to_vec(),
+        ip: "185.206.122.33/24".as_bytes().to_vec(),
+        gateway: "185.206.122.1".as_bytes().to_vec(),
         contract_id: 0,
     });
 
@@ -958,20 +3108,63 @@ fn prepare_farm_and_node() {
         mru: 1,
     };
 
-    let country = "Belgium".as_bytes().to_vec();
-    let city = "Ghent".as_bytes().to_vec();
+    TfgridModule::create_country(
+        RawOrigin::Root.into(),
+        "Belgium".as_bytes().to_vec(),
+        "BE".as_bytes().to_vec(),
+    )
+    .unwrap();
+    TfgridModule::create_city(RawOrigin::Root.into(), 1, "Ghent".as_bytes().to_vec()).unwrap();
+
+    // register the node at the start of the test chain so it is considered up
+    Timestamp::set_timestamp(1628082000 * 1000);
     TfgridModule::create_node(
         Origin::signed(alice()),
         1,
         resources,
         location,
-        country,
-        city,
+        1,
+        1,
         Vec::new(),
     )
     .unwrap();
 }
 
+fn prepare_gateway() {
+    let location = pallet_tfgrid_types::Location {
+        longitude: "12.233213231".as_bytes().to_vec(),
+        latitude: "32.323112123".as_bytes().to_vec(),
+    };
+
+    assert_ok!(TfgridModule::create_gateway(
+        Origin::signed(alice()),
+        1,
+        location,
+        1,
+        1,
+        "gateway_pub_key".as_bytes().to_vec(),
+        eve(),
+    ));
+}
+
+// adds 185.206.122.40 and 185.206.122.41 next to 185.206.122.33 on the farm
+fn add_farm_ips() {
+    assert_ok!(TfgridModule::add_farm_ip_range(
+        Origin::signed(alice()),
+        1,
+        "185.206.122.40/24".as_bytes().to_vec(),
+        2,
+        "185.206.122.1".as_bytes().to_vec()
+    ));
+}
+
+fn get_node_contract(contract_id: u64) -> types::NodeContract {
+    match SmartContractModule::contracts(contract_id).contract_type {
+        types::ContractData::NodeContract(node_contract) => node_contract,
+        _ => panic!("expected a node contract"),
+    }
+}
+
 fn run_to_block(n: u64) {
     Timestamp::set_timestamp((1628082000 * 1000) + (6000 * n));
     while System::block_number() < n {
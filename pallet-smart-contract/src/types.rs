@@ -1,6 +1,7 @@
 use codec::{Decode, Encode};
 use frame_support::traits::Vec;
-use substrate_fixed::types::U64F64;
+use sp_runtime::Perbill;
+use sp_std::vec;
 
 use pallet_tfgrid::types;
 
@@ -9,7 +10,8 @@ use pallet_tfgrid::types;
 pub enum PalletStorageVersion {
     V1,
     V2,
-    V3
+    V3,
+    V4,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
@@ -38,17 +40,32 @@ pub struct NodeContract {
     pub deployment_hash: Vec<u8>,
     pub public_ips: u32,
     pub public_ips_list: Vec<types::PublicIP>,
+    // prefix length of the requested ipv6 allocation (128 for a single address), 0 if none
+    pub public_ipv6_prefix_length: u8,
+    pub public_ipv6_list: Vec<types::PublicIPv6Allocation>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct NameContract {
     pub name: Vec<u8>,
+    pub pricing_policy_id: u32,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct DomainContract {
+    // fully qualified domain name owned by the user
+    pub domain: Vec<u8>,
+    // the gateway the domain points to
+    pub gateway_id: u32,
+    // the value the user has to publish in a TXT record on _tfgrid-challenge.<domain>
+    pub challenge: Vec<u8>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug)]
 pub enum ContractData {
     NodeContract(NodeContract),
     NameContract(NameContract),
+    DomainContract(DomainContract),
 }
 
 impl Default for ContractData {
@@ -68,12 +85,21 @@ pub struct ContractBillingInformation {
 pub enum ContractState {
     Created,
     Deleted(Cause),
+    Deployed,
+    // the twin could not pay the contract, holds the block at which the grace period started
+    GracePeriod(u64),
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug)]
 pub enum Cause {
     CanceledByUser,
-    OutOfFunds
+    OutOfFunds,
+    RejectedByNode,
+    DeploymentTimeout,
+    NodeFailure,
+    FarmDecommissioned,
+    PolicyViolation,
+    GatewayRemoved,
 }
 
 impl Default for ContractState {
@@ -82,6 +108,42 @@ impl Default for ContractState {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Encode, Decode, Debug)]
+pub enum DistributionDestination {
+    Foundation,
+    StakingPool,
+    SalesChannel,
+    Burn,
+}
+
+// The share of every billed amount that goes to a destination
+#[derive(PartialEq, Eq, Clone, Encode, Decode, Debug)]
+pub struct DistributionShare {
+    pub destination: DistributionDestination,
+    pub share: Perbill,
+}
+
+pub fn default_revenue_distribution() -> Vec<DistributionShare> {
+    vec![
+        DistributionShare {
+            destination: DistributionDestination::Foundation,
+            share: Perbill::from_percent(10),
+        },
+        DistributionShare {
+            destination: DistributionDestination::StakingPool,
+            share: Perbill::from_percent(5),
+        },
+        DistributionShare {
+            destination: DistributionDestination::SalesChannel,
+            share: Perbill::from_percent(50),
+        },
+        DistributionShare {
+            destination: DistributionDestination::Burn,
+            share: Perbill::from_percent(35),
+        },
+    ]
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug)]
 pub enum DiscountLevel {
     None,
@@ -97,15 +159,49 @@ impl Default for DiscountLevel {
     }
 }
 
-impl DiscountLevel {
-    pub fn price_multiplier(&self) -> U64F64 {
-        match self {
-            DiscountLevel::None => U64F64::from_num(1),
-            DiscountLevel::Default => U64F64::from_num(0.8),
-            DiscountLevel::Bronze => U64F64::from_num(0.7),
-            DiscountLevel::Silver => U64F64::from_num(0.6),
-            DiscountLevel::Gold => U64F64::from_num(0.4),
-        }
+// A discount level a twin gets when its balance covers a contract for at least `months` months,
+// the multiplier is the part of the price the twin pays
+#[derive(PartialEq, Eq, Clone, Encode, Decode, Default, Debug)]
+pub struct DiscountTier {
+    pub level: DiscountLevel,
+    pub months: u64,
+    pub multiplier: Perbill,
+}
+
+#[derive(PartialEq, Eq, Clone, Encode, Decode, Default, Debug)]
+pub struct DiscountConfiguration {
+    // sorted by months ascending
+    pub tiers: Vec<DiscountTier>,
+    // the part added to the price of certified capacity
+    pub certified_surcharge: Perbill,
+}
+
+// https://wiki.threefold.io/#/threefold__grid_pricing
+pub fn default_discount_configuration() -> DiscountConfiguration {
+    DiscountConfiguration {
+        tiers: vec![
+            DiscountTier {
+                level: DiscountLevel::Default,
+                months: 3,
+                multiplier: Perbill::from_percent(80),
+            },
+            DiscountTier {
+                level: DiscountLevel::Bronze,
+                months: 6,
+                multiplier: Perbill::from_percent(70),
+            },
+            DiscountTier {
+                level: DiscountLevel::Silver,
+                months: 12,
+                multiplier: Perbill::from_percent(60),
+            },
+            DiscountTier {
+                level: DiscountLevel::Gold,
+                months: 36,
+                multiplier: Perbill::from_percent(40),
+            },
+        ],
+        certified_surcharge: Perbill::from_percent(25),
     }
 }
 
@@ -127,3 +223,45 @@ pub struct ContractBill {
     pub discount_level: DiscountLevel,
     pub amount_billed: u128,
 }
+
+pub mod deprecated {
+    use codec::{Decode, Encode};
+    use frame_support::traits::Vec;
+
+    use pallet_tfgrid::types;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+    pub struct ContractV1 {
+        pub version: u32,
+        pub state: super::ContractState,
+        pub contract_id: u64,
+        pub twin_id: u32,
+        pub contract_type: ContractDataV1,
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug)]
+    pub enum ContractDataV1 {
+        NodeContract(NodeContractV1),
+        NameContract(NameContractV1),
+    }
+
+    impl Default for ContractDataV1 {
+        fn default() -> ContractDataV1 {
+            ContractDataV1::NodeContract(NodeContractV1::default())
+        }
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+    pub struct NodeContractV1 {
+        pub node_id: u32,
+        pub deployment_data: Vec<u8>,
+        pub deployment_hash: Vec<u8>,
+        pub public_ips: u32,
+        pub public_ips_list: Vec<types::PublicIP>,
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+    pub struct NameContractV1 {
+        pub name: Vec<u8>,
+    }
+}
@@ -9,12 +9,13 @@ use frame_support::{
     debug, decl_error, decl_event, decl_module, decl_storage, dispatch, ensure,
     traits::Get,
     traits::{Currency, ExistenceRequirement::KeepAlive, EnsureOrigin},
+    weights::Weight,
 };
 use frame_system::{self as system, ensure_signed, RawOrigin};
 use hex::FromHex;
 use pallet_timestamp as timestamp;
-use sp_runtime::traits::SaturatedConversion;
-use sp_std::prelude::*;
+use sp_runtime::{traits::SaturatedConversion, Perbill};
+use sp_std::{collections::btree_map::BTreeMap, prelude::*};
 
 #[cfg(test)]
 mod tests;
@@ -33,25 +34,57 @@ pub trait Config: system::Config + timestamp::Config {
 	/// Origin for restricted extrinsics
 	/// Can be the root or another origin configured in the runtime
 	type RestrictedOrigin: EnsureOrigin<Self::Origin>;
+	/// Number of seconds a node can go without reporting its uptime
+	/// before it is considered down
+	type HeartbeatWindow: Get<u64>;
+	/// Length in seconds of a period over which node uptime is accounted
+	type UptimePeriod: Get<u64>;
 }
 
 // Version constant that referenced the struct version
-pub const TFGRID_ENTITY_VERSION: u32 = 1;
-pub const TFGRID_FARM_VERSION: u32 = 1;
+pub const TFGRID_ENTITY_VERSION: u32 = 2;
+pub const TFGRID_FARM_VERSION: u32 = 2;
 pub const TFGRID_TWIN_VERSION: u32 = 1;
-pub const TFGRID_NODE_VERSION: u32 = 3;
+pub const TFGRID_NODE_VERSION: u32 = 4;
 pub const TFGRID_PRICING_POLICY_VERSION: u32 = 1;
 pub const TFGRID_CERTIFICATION_CODE_VERSION: u32 = 1;
 pub const TFGRID_FARMING_POLICY_VERSION: u32 = 1;
+pub const TFGRID_GATEWAY_VERSION: u32 = 1;
+
+// Maximum number of uptime periods kept in the uptime history of a node
+pub const MAX_UPTIME_PERIODS: usize = 30;
+// maximum number of public ips that can be added or removed in one call
+pub const MAX_IP_RANGE_SIZE: u32 = 256;
+// maximum number of public ips on a farm
+pub const MAX_FARM_PUBLIC_IPS: u32 = 1024;
+// weight of checking one ip of a range against one ip of the farm
+pub const IP_CHECK_WEIGHT: Weight = 100_000;
+// shortest prefix of the subnet of a public ip, larger subnets are not handed to a farm
+pub const MIN_PUBLIC_IP_PREFIX: u8 = 16;
 
 decl_storage! {
     trait Store for Module<T: Config> as TfgridModule {
         pub Farms get(fn farms): map hasher(blake2_128_concat) u32 => types::Farm;
         pub FarmIdByName get(fn farms_by_name_id): map hasher(blake2_128_concat) Vec<u8> => u32;
         pub FarmPayoutV2AddressByFarmID get(fn farm_payout_address_by_farm_id): map hasher(blake2_128_concat) u32 => Vec<u8>;
+        // pending farm transfers, farm id => id of the twin the farm is offered to
+        pub FarmTransferOffers get(fn farm_transfer_offers): map hasher(blake2_128_concat) u32 => u32;
+        // public ips (by farm id and ip) that are removed from the farm once their contract is canceled
+        pub FarmIPsRetiring get(fn farm_ips_retiring): double_map hasher(blake2_128_concat) u32, hasher(blake2_128_concat) Vec<u8> => bool;
 
         pub Nodes get(fn nodes): map hasher(blake2_128_concat) u32 => types::Node;
         pub NodeIdByTwinID get(fn node_by_twin_id): map hasher(blake2_128_concat) u32 => u32;
+        pub NodeAvailabilityByID get(fn node_availability_by_id): map hasher(blake2_128_concat) u32 => types::NodeAvailability;
+        pub NodeUptimeTrackerByID get(fn node_uptime_tracker_by_id): map hasher(blake2_128_concat) u32 => types::NodeUptimeTracker;
+        pub NodeUptimeHistoryByID get(fn node_uptime_history_by_id): map hasher(blake2_128_concat) u32 => Vec<types::UptimeBucket>;
+
+        pub Countries get(fn countries): map hasher(blake2_128_concat) u32 => types::Country;
+        pub CountryIdByCode get(fn country_id_by_code): map hasher(blake2_128_concat) Vec<u8> => u32;
+        pub Cities get(fn cities): map hasher(blake2_128_concat) u32 => types::City;
+        pub CityIdByCountryAndName get(fn city_id_by_country_and_name): double_map hasher(blake2_128_concat) u32, hasher(blake2_128_concat) Vec<u8> => u32;
+
+        pub Gateways get(fn gateways): map hasher(blake2_128_concat) u32 => types::Gateway<T::AccountId>;
+        pub GatewayIdsByFarmID get(fn gateway_ids_by_farm_id): map hasher(blake2_128_concat) u32 => Vec<u32>;
 
         pub Entities get(fn entities): map hasher(blake2_128_concat) u32 => types::Entity<T::AccountId>;
         pub EntityIdByAccountID get(fn entities_by_pubkey_id): map hasher(blake2_128_concat) T::AccountId => u32;
@@ -59,6 +92,10 @@ decl_storage! {
 
         pub Twins get(fn twins): map hasher(blake2_128_concat) u32 => types::Twin<T::AccountId>;
         pub TwinIdByAccountID get(fn twin_ids_by_pubkey): map hasher(blake2_128_concat) T::AccountId => u32;
+        // number of key rotations of a twin, part of the signed message so a rotation cannot be replayed
+        pub TwinKeyRotationNonce get(fn twin_key_rotation_nonce): map hasher(blake2_128_concat) u32 => u64;
+        // accounts allowed to act on behalf of a twin, twin id => account id => delegation
+        pub TwinDelegations get(fn twin_delegations): double_map hasher(blake2_128_concat) u32, hasher(blake2_128_concat) T::AccountId => types::Delegation;
 
         pub PricingPolicies get(fn pricing_policies): map hasher(blake2_128_concat) u32 => types::PricingPolicy<T::AccountId>;
         pub PricingPolicyIdByName get(fn pricing_policies_by_name_id): map hasher(blake2_128_concat) Vec<u8> => u32;
@@ -80,9 +117,12 @@ decl_storage! {
         PricingPolicyID: u32;
         CertificationCodeID: u32;
         FarmingPolicyID: u32;
+        GatewayID: u32;
+        CountryID: u32;
+        CityID: u32;
 
         /// The current version of the pallet.
-        PalletVersion: types::StorageVersion = types::StorageVersion::V1Struct;
+        PalletVersion get(fn pallet_version): types::StorageVersion = types::StorageVersion::V1Struct;
     }
 
     add_extra_genesis {
@@ -110,6 +150,9 @@ decl_storage! {
         config(farming_policy_certified_ipu): u32;
 
         build(|_config| {
+            // a new chain starts with the latest storage layout
+            PalletVersion::put(types::StorageVersion::V4Struct);
+
             let foundation_account = _config.foundation_account.clone();
             let sales_account = _config.sales_account.clone();
 
@@ -189,12 +232,25 @@ decl_event!(
         FarmStored(types::Farm),
         FarmUpdated(types::Farm),
         FarmDeleted(u32),
+        FarmTransferOffered(u32, u32, u32),
+        FarmTransferCanceled(u32),
+        FarmTransferred(u32, u32, u32),
+        // farm id, first ip of the range, number of ips
+        FarmIPRangeAdded(u32, Vec<u8>, u32),
+        FarmIPRangeRemoved(u32, Vec<u8>, u32),
+        FarmIPRetiring(u32, Vec<u8>),
 
         NodeStored(types::Node),
         NodeUpdated(types::Node),
         NodeDeleted(u32),
         NodeUptimeReported(u32, u64, u64),
         NodePublicConfigStored(u32, types::PublicConfig),
+        NodeStatusChanged(u32, types::NodeStatus),
+        NodeRebooted(u32, u64),
+
+        GatewayStored(types::Gateway<AccountId>),
+        GatewayUpdated(types::Gateway<AccountId>),
+        GatewayDeleted(u32),
 
         EntityStored(types::Entity<AccountId>),
         EntityUpdated(types::Entity<AccountId>),
@@ -206,11 +262,19 @@ decl_event!(
         TwinEntityStored(u32, u32, Vec<u8>),
         TwinEntityRemoved(u32, u32),
         TwinDeleted(u32),
+        TwinAccountRotated(u32, AccountId, AccountId),
+        DelegationGranted(u32, AccountId, types::Delegation),
+        DelegationRevoked(u32, AccountId),
 
         PricingPolicyStored(types::PricingPolicy<AccountId>),
         CertificationCodeStored(types::CertificationCodes),
         FarmingPolicyStored(types::FarmingPolicy),
         FarmPayoutV2AddressRegistered(u32, Vec<u8>),
+
+        CountryStored(types::Country),
+        CountryUpdated(types::Country),
+        CityStored(types::City),
+        CityUpdated(types::City),
     }
 );
 
@@ -225,6 +289,9 @@ decl_error! {
         CannotDeleteNode,
         NodeDeleteNotAuthorized,
         NodeUpdateNotAuthorized,
+        NodeIsDecommissioned,
+
+        GatewayNotExists,
 
         FarmExists,
         FarmNotExists,
@@ -233,9 +300,27 @@ decl_error! {
         CannotDeleteFarm,
         CannotDeleteFarmWithPublicIPs,
         CannotDeleteFarmWithNodesAssigned,
+        CannotDeleteFarmWithGatewaysAssigned,
         CannotDeleteFarmWrongTwin,
         IpExists,
         IpNotExists,
+        InvalidPublicIP,
+        InvalidGatewayIP,
+        PublicIPIsReserved,
+        GatewayIPNotInSubnet,
+        GatewayIPIsPublicIP,
+        PublicIPPrefixTooShort,
+        PublicIPIsNetworkOrBroadcastAddress,
+        InvalidIPRange,
+        CannotRemoveReservedIP,
+        InvalidPublicIPv6Prefix,
+        InvalidGatewayIPv6,
+        PublicIPv6PrefixIsReserved,
+        GatewayIPv6NotInPrefix,
+        PublicIPv6PrefixInUse,
+        FarmTransferToSameTwin,
+        FarmTransferNotOffered,
+        FarmTransferNotAuthorized,
 
         EntityWithNameExists,
         EntityWithPubkeyExists,
@@ -251,6 +336,13 @@ decl_error! {
         TwinWithPubkeyExists,
         CannotCreateTwin,
         UnauthorizedToUpdateTwin,
+        TwinKeySignatureDoesNotMatch,
+        DelegationNotExists,
+        DelegationWithoutPermissions,
+        TooManyDelegatePermissions,
+        TooManyFarmPublicIPs,
+        DelegationExpiryInThePast,
+        CannotDelegateToSelf,
 
         PricingPolicyExists,
         PricingPolicyNotExists,
@@ -262,6 +354,16 @@ decl_error! {
         UserDidNotSignTermsAndConditions,
         FarmerDidNotSignTermsAndConditions,
         FarmerNotAuthorized,
+
+        CountryExists,
+        CountryNotExists,
+        InvalidCountryCode,
+        CityExists,
+        CityNotExists,
+        CityNotInCountry,
+
+        InvalidLatitude,
+        InvalidLongitude,
     }
 }
 
@@ -271,6 +373,20 @@ decl_module! {
 
         fn deposit_event() = default;
 
+        fn on_runtime_upgrade() -> Weight {
+            Self::migrate_storage()
+        }
+
+        fn integrity_test() {
+            assert!(T::UptimePeriod::get() > 0, "UptimePeriod must be greater than 0");
+        }
+
+        /// Number of seconds a node can go without reporting its uptime before it is considered down
+        const HeartbeatWindow: u64 = T::HeartbeatWindow::get();
+
+        /// Length in seconds of a period over which node uptime is accounted
+        const UptimePeriod: u64 = T::UptimePeriod::get();
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
         pub fn user_accept_tc(origin, document_link: Vec<u8>, document_hash: Vec<u8>) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
@@ -328,19 +444,15 @@ decl_module! {
 
             // reset all public ip contract id's
             // just a safeguard
-            // filter out doubles
+            // validate and filter out doubles
             let mut pub_ips: Vec<types::PublicIP> = Vec::new();
             for ip in public_ips {
-                match pub_ips.iter().position(|pub_ip| pub_ip.ip == ip.ip) {
-                    Some(_) => return Err(Error::<T>::IpExists.into()),
-                    None => {
-                        pub_ips.push(types::PublicIP{
-                            ip: ip.ip,
-                            gateway: ip.gateway,
-                            contract_id: 0
-                        });
-                    }
-                };
+                Self::ensure_can_add_public_ip(&pub_ips, &ip)?;
+                pub_ips.push(types::PublicIP{
+                    ip: ip.ip,
+                    gateway: ip.gateway,
+                    contract_id: 0
+                });
             };
 
             let new_farm = types::Farm {
@@ -351,6 +463,7 @@ decl_module! {
                 pricing_policy_id: 1,
                 certification_type: types::CertificationType::Diy,
                 public_ips: pub_ips,
+                public_ipv6_prefixes: Vec::new(),
             };
 
             Farms::insert(id, &new_farm);
@@ -366,13 +479,10 @@ decl_module! {
         pub fn update_farm(origin, id: u32, name: Vec<u8>, pricing_policy_id: u32) -> dispatch::DispatchResult {
             let address = ensure_signed(origin)?;
 
-            ensure!(TwinIdByAccountID::<T>::contains_key(&address), Error::<T>::TwinNotExists);
-            let twin_id = TwinIdByAccountID::<T>::get(&address);
-
             ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
             let farm = Farms::get(id);
 
-            ensure!(farm.twin_id == twin_id, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(Self::is_authorized_for_twin(&address, farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
 
             let mut stored_farm = Farms::get(id);
             // Remove stored farm by name and insert new one
@@ -408,6 +518,78 @@ decl_module! {
             Ok(())
         }
 
+        // The twin owning a farm offers it to another twin, the transfer only happens
+        // once the receiving twin accepts it
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn offer_farm_transfer(origin, farm_id: u32, twin_id: u32) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&address), Error::<T>::TwinNotExists);
+            let owner_twin_id = TwinIdByAccountID::<T>::get(&address);
+
+            ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            let farm = Farms::get(farm_id);
+
+            ensure!(farm.twin_id == owner_twin_id, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(Twins::<T>::contains_key(twin_id), Error::<T>::TwinNotExists);
+            ensure!(twin_id != owner_twin_id, Error::<T>::FarmTransferToSameTwin);
+
+            // a new offer replaces any pending one
+            FarmTransferOffers::insert(farm_id, twin_id);
+
+            Self::deposit_event(RawEvent::FarmTransferOffered(farm_id, owner_twin_id, twin_id));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn cancel_farm_transfer(origin, farm_id: u32) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&address), Error::<T>::TwinNotExists);
+            let owner_twin_id = TwinIdByAccountID::<T>::get(&address);
+
+            ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            let farm = Farms::get(farm_id);
+
+            ensure!(farm.twin_id == owner_twin_id, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(FarmTransferOffers::contains_key(farm_id), Error::<T>::FarmTransferNotOffered);
+
+            FarmTransferOffers::remove(farm_id);
+
+            Self::deposit_event(RawEvent::FarmTransferCanceled(farm_id));
+
+            Ok(())
+        }
+
+        // The receiving twin accepts a farm transfer, it must have accepted the farmer
+        // terms and conditions. The payout address of the farm is kept as is.
+        #[weight = 10 + T::DbWeight::get().writes(2)]
+        pub fn accept_farm_transfer(origin, farm_id: u32) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(FarmersTermsAndConditions::<T>::contains_key(address.clone()), Error::<T>::FarmerDidNotSignTermsAndConditions);
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&address), Error::<T>::TwinNotExists);
+            let twin_id = TwinIdByAccountID::<T>::get(&address);
+
+            ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            ensure!(FarmTransferOffers::contains_key(farm_id), Error::<T>::FarmTransferNotOffered);
+            ensure!(FarmTransferOffers::get(farm_id) == twin_id, Error::<T>::FarmTransferNotAuthorized);
+
+            let mut stored_farm = Farms::get(farm_id);
+            let previous_twin_id = stored_farm.twin_id;
+            stored_farm.twin_id = twin_id;
+
+            Farms::insert(farm_id, &stored_farm);
+            FarmTransferOffers::remove(farm_id);
+
+            Self::deposit_event(RawEvent::FarmTransferred(farm_id, previous_twin_id, twin_id));
+            Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
+
+            Ok(())
+        }
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
         pub fn set_farm_certification(origin, farm_id: u32, certification_type: types::CertificationType) -> dispatch::DispatchResult {
             T::RestrictedOrigin::ensure_origin(origin)?;
@@ -429,8 +611,7 @@ decl_module! {
             ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
             let mut stored_farm = Farms::get(id);
 
-            let twin = Twins::<T>::get(stored_farm.twin_id);
-            ensure!(twin.account_id == address, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
 
             let new_ip = types::PublicIP {
                 ip,
@@ -438,30 +619,171 @@ decl_module! {
                 contract_id: 0
             };
 
-            match stored_farm.public_ips.iter().position(|public_ip| public_ip.ip == new_ip.ip) {
-                Some(_) => return Err(Error::<T>::IpExists.into()),
-                None => {
-                    stored_farm.public_ips.push(new_ip);
+            Self::ensure_can_add_public_ip(&stored_farm.public_ips, &new_ip)?;
+
+            stored_farm.public_ips.push(new_ip);
+            Farms::insert(stored_farm.id, &stored_farm);
+            Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn remove_farm_ip(origin, id: u32, ip: Vec<u8>) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
+            let mut stored_farm = Farms::get(id);
+
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+
+            match stored_farm.public_ips.iter().position(|pubip| pubip.ip == ip) {
+                Some(index) => {
+                    // reserved ips can only be retired, see `retire_farm_ip`
+                    ensure!(stored_farm.public_ips[index].contract_id == 0, Error::<T>::CannotRemoveReservedIP);
+                    stored_farm.public_ips.remove(index);
+                    Farms::insert(stored_farm.id, &stored_farm);
+                    Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
+                    Ok(())
+                },
+                None => Err(Error::<T>::IpNotExists.into()),
+            }
+        }
+
+        // Removes a public ip that is reserved by a contract once that contract is canceled,
+        // until then the ip is marked as retiring. A free ip is removed right away.
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn retire_farm_ip(origin, id: u32, ip: Vec<u8>) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
+            let mut stored_farm = Farms::get(id);
+
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+
+            match stored_farm.public_ips.iter().position(|pubip| pubip.ip == ip) {
+                Some(index) if stored_farm.public_ips[index].contract_id == 0 => {
+                    stored_farm.public_ips.remove(index);
                     Farms::insert(stored_farm.id, &stored_farm);
                     Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
-                    return Ok(())
+                    Ok(())
+                },
+                Some(_) => {
+                    FarmIPsRetiring::insert(id, &ip, true);
+                    Self::deposit_event(RawEvent::FarmIPRetiring(id, ip));
+                    Ok(())
+                },
+                None => Err(Error::<T>::IpNotExists.into()),
+            }
+        }
+
+        // Adds `count` consecutive public ips starting from `start_ip` (in cidr notation) which
+        // share the same gateway. Either all ips are added or none.
+        // The weight of a full farm is charged, what the farm's ips did not need is refunded.
+        #[weight = Module::<T>::ip_range_weight(*count, MAX_FARM_PUBLIC_IPS)]
+        pub fn add_farm_ip_range(origin, id: u32, start_ip: Vec<u8>, count: u32, gateway: Vec<u8>) -> dispatch::DispatchResultWithPostInfo {
+            let address = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
+            let mut stored_farm = Farms::get(id);
+
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+            let farm_ips = stored_farm.public_ips.len() as u32;
+
+            for cidr in Self::get_ip_range(&start_ip, count)? {
+                let new_ip = types::PublicIP {
+                    ip: cidr.to_bytes(),
+                    gateway: gateway.clone(),
+                    contract_id: 0
+                };
+                Self::ensure_can_add_public_ip(&stored_farm.public_ips, &new_ip)?;
+                stored_farm.public_ips.push(new_ip);
+            }
+
+            Farms::insert(stored_farm.id, &stored_farm);
+            Self::deposit_event(RawEvent::FarmIPRangeAdded(stored_farm.id, start_ip, count));
+
+            Ok(Some(Self::ip_range_weight(count, farm_ips)).into())
+        }
+
+        // Removes `count` consecutive public ips starting from `start_ip`. Fails without removing
+        // anything if one of the ips is not on the farm or is used by a contract.
+        #[weight = Module::<T>::ip_range_weight(*count, MAX_FARM_PUBLIC_IPS)]
+        pub fn remove_farm_ip_range(origin, id: u32, start_ip: Vec<u8>, count: u32) -> dispatch::DispatchResultWithPostInfo {
+            let address = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
+            let mut stored_farm = Farms::get(id);
+
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+            let farm_ips = stored_farm.public_ips.len() as u32;
+
+            for cidr in Self::get_ip_range(&start_ip, count)? {
+                match stored_farm.public_ips.iter().position(|pubip| {
+                    types::Ipv4Cidr::parse(&pubip.ip).map(|ip| ip.address) == Some(cidr.address)
+                }) {
+                    Some(index) => {
+                        ensure!(stored_farm.public_ips[index].contract_id == 0, Error::<T>::CannotRemoveReservedIP);
+                        stored_farm.public_ips.remove(index);
+                    },
+                    None => return Err(Error::<T>::IpNotExists.into()),
                 }
-            };
+            }
+
+            Farms::insert(stored_farm.id, &stored_farm);
+            Self::deposit_event(RawEvent::FarmIPRangeRemoved(stored_farm.id, start_ip, count));
+
+            Ok(Some(Self::ip_range_weight(count, farm_ips)).into())
         }
 
         #[weight = 10 + T::DbWeight::get().writes(1)]
-        pub fn remove_farm_ip(origin, id: u32, ip: Vec<u8>) -> dispatch::DispatchResult {
+        pub fn add_farm_ipv6_prefix(origin, id: u32, prefix: Vec<u8>, gateway: Vec<u8>) -> dispatch::DispatchResult {
             let address = ensure_signed(origin)?;
 
             ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
             let mut stored_farm = Farms::get(id);
 
-            let twin = Twins::<T>::get(stored_farm.twin_id);
-            ensure!(twin.account_id == address, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+
+            let cidr = types::Ipv6Cidr::parse(&prefix).ok_or(Error::<T>::InvalidPublicIPv6Prefix)?;
+            // the prefix must not have host bits set and must leave room for allocations
+            ensure!(cidr.address == cidr.first() && cidr.prefix <= 64, Error::<T>::InvalidPublicIPv6Prefix);
+            ensure!(!types::is_reserved_ipv6(cidr.address), Error::<T>::PublicIPv6PrefixIsReserved);
+            let gateway_address = types::parse_ipv6(&gateway).ok_or(Error::<T>::InvalidGatewayIPv6)?;
+            ensure!(cidr.contains(gateway_address), Error::<T>::GatewayIPv6NotInPrefix);
+
+            for public_prefix in &stored_farm.public_ipv6_prefixes {
+                if let Some(existing) = types::Ipv6Cidr::parse(&public_prefix.prefix) {
+                    ensure!(!existing.contains(cidr.address) && !cidr.contains(existing.address), Error::<T>::IpExists);
+                }
+            }
 
-            match stored_farm.public_ips.iter().position(|pubip| pubip.ip == ip && pubip.contract_id == 0) {
+            stored_farm.public_ipv6_prefixes.push(types::PublicIPv6Prefix {
+                prefix: cidr.to_bytes(),
+                gateway: types::format_ipv6(gateway_address),
+                allocations: Vec::new(),
+            });
+            Farms::insert(stored_farm.id, &stored_farm);
+            Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn remove_farm_ipv6_prefix(origin, id: u32, prefix: Vec<u8>) -> dispatch::DispatchResult {
+            let address = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(id), Error::<T>::FarmNotExists);
+            let mut stored_farm = Farms::get(id);
+
+            ensure!(Self::is_authorized_for_twin(&address, stored_farm.twin_id, types::DelegatePermission::FarmManagement), Error::<T>::CannotUpdateFarmWrongTwin);
+
+            let cidr = types::Ipv6Cidr::parse(&prefix).ok_or(Error::<T>::InvalidPublicIPv6Prefix)?;
+            match stored_farm.public_ipv6_prefixes.iter().position(|public_prefix| types::Ipv6Cidr::parse(&public_prefix.prefix) == Some(cidr)) {
                 Some(index) => {
-                    stored_farm.public_ips.remove(index);
+                    // prefixes with allocations are still in use by contracts
+                    ensure!(stored_farm.public_ipv6_prefixes[index].allocations.is_empty(), Error::<T>::PublicIPv6PrefixInUse);
+                    stored_farm.public_ipv6_prefixes.remove(index);
                     Farms::insert(stored_farm.id, &stored_farm);
                     Self::deposit_event(RawEvent::FarmUpdated(stored_farm));
                     Ok(())
@@ -478,17 +800,21 @@ decl_module! {
             let stored_farm = Farms::get(id);
             // make sure farm doesn't have public ips assigned
             ensure!(stored_farm.public_ips.len() == 0, Error::<T>::CannotDeleteFarmWithPublicIPs);
+            ensure!(stored_farm.public_ipv6_prefixes.is_empty(), Error::<T>::CannotDeleteFarmWithPublicIPs);
             // make sure farm doesn't have nodes assigned
             for (_, node) in Nodes::iter(){
                 if node.farm_id == id {
                     return Err(Error::<T>::CannotDeleteFarmWithNodesAssigned.into())
                 }
             }
+            // make sure farm doesn't have gateways assigned
+            ensure!(GatewayIdsByFarmID::get(id).is_empty(), Error::<T>::CannotDeleteFarmWithGatewaysAssigned);
             let twin = Twins::<T>::get(stored_farm.twin_id);
             ensure!(twin.account_id == address, Error::<T>::CannotDeleteFarmWrongTwin);
 
             // delete farm
             Farms::remove(id);
+            FarmTransferOffers::remove(id);
 
             // Remove stored farm by name and insert new one
             FarmIdByName::remove(stored_farm.name);
@@ -499,10 +825,12 @@ decl_module! {
         }
 
         #[weight = 10 + T::DbWeight::get().writes(1)]
-        pub fn create_node(origin, farm_id: u32, resources: types::Resources, location: types::Location, country: Vec<u8>, city: Vec<u8>, interfaces: Vec<types::Interface>) -> dispatch::DispatchResult {
+        pub fn create_node(origin, farm_id: u32, resources: types::Resources, location: types::Location, country_id: u32, city_id: u32, interfaces: Vec<types::Interface>) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
 
             ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
+            let location = Self::parse_location(&location)?;
             let farm = Farms::get(farm_id);
             ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
             let twin_id = TwinIdByAccountID::<T>::get(&account_id);
@@ -532,8 +860,8 @@ decl_module! {
                 twin_id,
                 resources,
                 location,
-                country,
-                city,
+                country_id,
+                city_id,
                 public_config: None,
                 created,
                 farming_policy_id,
@@ -545,31 +873,37 @@ decl_module! {
             NodeID::put(id);
             NodeIdByTwinID::insert(twin_id, new_node.id);
 
+            // a freshly registered node is online
+            NodeAvailabilityByID::insert(id, types::NodeAvailability {
+                status: types::NodeStatus::Up,
+                last_seen: created,
+            });
+
             Self::deposit_event(RawEvent::NodeStored(new_node));
 
             Ok(())
         }
 
         #[weight = 10 + T::DbWeight::get().writes(1)]
-        pub fn update_node(origin, node_id: u32, farm_id: u32, resources: types::Resources, location: types::Location, country: Vec<u8>, city: Vec<u8>, interfaces: Vec<types::Interface>) -> dispatch::DispatchResult {
+        pub fn update_node(origin, node_id: u32, farm_id: u32, resources: types::Resources, location: types::Location, country_id: u32, city_id: u32, interfaces: Vec<types::Interface>) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
 
             ensure!(Nodes::contains_key(&node_id), Error::<T>::NodeNotExists);
-            ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
 
-            let twin_id = TwinIdByAccountID::<T>::get(&account_id);
             let node = Nodes::get(&node_id);
-            ensure!(node.twin_id == twin_id, Error::<T>::NodeUpdateNotAuthorized);
+            ensure!(Self::is_authorized_for_twin(&account_id, node.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::NodeUpdateNotAuthorized);
 
             ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
+            let location = Self::parse_location(&location)?;
 
             let mut stored_node = Nodes::get(node_id);
 
             stored_node.farm_id = farm_id;
             stored_node.resources = resources;
             stored_node.location = location;
-            stored_node.country = country;
-            stored_node.city = city;
+            stored_node.country_id = country_id;
+            stored_node.city_id = city_id;
             stored_node.interfaces = interfaces;
 
             // override node in storage
@@ -587,19 +921,18 @@ decl_module! {
         pub fn delete_node_farm(origin, node_id: u32) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
 
-            ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
             ensure!(Nodes::contains_key(&node_id), Error::<T>::NodeNotExists);
 
             // check if the farmer twin is authorized
-            let farm_twin_id = TwinIdByAccountID::<T>::get(&account_id);
-            // check if the ndode belong to said farm
             let node = Nodes::get(&node_id);
             let farm = Farms::get(node.farm_id);
-            let farm_twin = Twins::<T>::get(farm.twin_id);
-            ensure!(farm_twin_id == farm_twin.id, Error::<T>::FarmerNotAuthorized);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
 
 
             Nodes::remove(node_id);
+            NodeAvailabilityByID::remove(node_id);
+            NodeUptimeTrackerByID::remove(node_id);
+            NodeUptimeHistoryByID::remove(node_id);
 
             Ok(())
         }
@@ -633,9 +966,22 @@ decl_module! {
 
             ensure!(Nodes::contains_key(node_id), Error::<T>::NodeNotExists);
 
+            let mut availability = NodeAvailabilityByID::get(node_id);
+            ensure!(availability.status != types::NodeStatus::Decommissioned, Error::<T>::NodeIsDecommissioned);
+            let previous_status = Self::get_node_status(node_id);
+
             let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
 
+            Self::account_node_uptime(node_id, now, uptime);
+
+            availability.status = types::NodeStatus::Up;
+            availability.last_seen = now;
+            NodeAvailabilityByID::insert(node_id, &availability);
+
             Self::deposit_event(RawEvent::NodeUptimeReported(node_id, now, uptime));
+            if previous_status != types::NodeStatus::Up {
+                Self::deposit_event(RawEvent::NodeStatusChanged(node_id, types::NodeStatus::Up));
+            }
 
             // refund node wallet if needed
             Self::fund_node_wallet(node_id);
@@ -643,6 +989,52 @@ decl_module! {
             Ok(())
         }
 
+        // A farmer can put a node on standby (powered off to save energy), the node
+        // is brought back up on its next uptime report
+        #[weight = 10 + T::DbWeight::get().writes(1) + T::DbWeight::get().reads(4)]
+        pub fn set_node_standby(origin, node_id: u32) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(Nodes::contains_key(node_id), Error::<T>::NodeNotExists);
+
+            let node = Nodes::get(node_id);
+            let farm = Farms::get(node.farm_id);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
+
+            let mut availability = NodeAvailabilityByID::get(node_id);
+            ensure!(availability.status != types::NodeStatus::Decommissioned, Error::<T>::NodeIsDecommissioned);
+
+            availability.status = types::NodeStatus::Standby;
+            NodeAvailabilityByID::insert(node_id, &availability);
+
+            Self::deposit_event(RawEvent::NodeStatusChanged(node_id, types::NodeStatus::Standby));
+
+            Ok(())
+        }
+
+        // A farmer can take a node out of service for good, a decommissioned node
+        // cannot report uptime anymore
+        #[weight = 10 + T::DbWeight::get().writes(1) + T::DbWeight::get().reads(4)]
+        pub fn decommission_node(origin, node_id: u32) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(Nodes::contains_key(node_id), Error::<T>::NodeNotExists);
+
+            let node = Nodes::get(node_id);
+            let farm = Farms::get(node.farm_id);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
+
+            let mut availability = NodeAvailabilityByID::get(node_id);
+            ensure!(availability.status != types::NodeStatus::Decommissioned, Error::<T>::NodeIsDecommissioned);
+
+            availability.status = types::NodeStatus::Decommissioned;
+            NodeAvailabilityByID::insert(node_id, &availability);
+
+            Self::deposit_event(RawEvent::NodeStatusChanged(node_id, types::NodeStatus::Decommissioned));
+
+            Ok(())
+        }
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
         pub fn add_node_public_config(origin, farm_id: u32, node_id: u32, public_config: types::PublicConfig) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
@@ -650,8 +1042,7 @@ decl_module! {
             // check if this twin can update the farm with id passed
             ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
             let farm = Farms::get(farm_id);
-            let farm_twin = Twins::<T>::get(farm.twin_id);
-            ensure!(farm_twin.account_id == account_id, Error::<T>::CannotUpdateFarmWrongTwin);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::CannotUpdateFarmWrongTwin);
 
             // check if the node belong to the farm
             ensure!(Nodes::contains_key(node_id), Error::<T>::NodeNotExists);
@@ -677,30 +1068,118 @@ decl_module! {
             ensure!(Nodes::contains_key(id), Error::<T>::NodeNotExists);
 
             let stored_node = Nodes::get(id);
-            let twin_id = TwinIdByAccountID::<T>::get(&account_id);
-            ensure!(stored_node.twin_id == twin_id, Error::<T>::NodeUpdateNotAuthorized);
+            ensure!(Self::is_authorized_for_twin(&account_id, stored_node.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::NodeUpdateNotAuthorized);
 
             Nodes::remove(id);
+            NodeAvailabilityByID::remove(id);
+            NodeUptimeTrackerByID::remove(id);
+            NodeUptimeHistoryByID::remove(id);
 
             Self::deposit_event(RawEvent::NodeDeleted(id));
 
             Ok(())
         }
 
+        #[weight = 10 + T::DbWeight::get().writes(3)]
+        pub fn create_gateway(origin, farm_id: u32, location: types::Location, country_id: u32, city_id: u32, pub_key: Vec<u8>, gateway_account_id: T::AccountId) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(Farms::contains_key(farm_id), Error::<T>::FarmNotExists);
+            let farm = Farms::get(farm_id);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
+            let location = Self::parse_location(&location)?;
+
+            let id = GatewayID::get() + 1;
+
+            let gateway = types::Gateway {
+                version: TFGRID_GATEWAY_VERSION,
+                id,
+                farm_id,
+                location,
+                country_id,
+                city_id,
+                pub_key,
+                account_id: gateway_account_id,
+            };
+
+            Gateways::<T>::insert(id, &gateway);
+            GatewayID::put(id);
+
+            let mut gateway_ids = GatewayIdsByFarmID::get(farm_id);
+            gateway_ids.push(id);
+            GatewayIdsByFarmID::insert(farm_id, gateway_ids);
+
+            Self::deposit_event(RawEvent::GatewayStored(gateway));
+
+            Ok(())
+        }
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
-        pub fn create_entity(origin, target: T::AccountId, name: Vec<u8>, country: Vec<u8>, city: Vec<u8>, signature: Vec<u8>) -> dispatch::DispatchResult {
+        pub fn update_gateway(origin, gateway_id: u32, location: types::Location, country_id: u32, city_id: u32, pub_key: Vec<u8>, gateway_account_id: T::AccountId) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(Gateways::<T>::contains_key(gateway_id), Error::<T>::GatewayNotExists);
+            let mut stored_gateway = Gateways::<T>::get(gateway_id);
+
+            let farm = Farms::get(stored_gateway.farm_id);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
+            let location = Self::parse_location(&location)?;
+
+            stored_gateway.location = location;
+            stored_gateway.country_id = country_id;
+            stored_gateway.city_id = city_id;
+            stored_gateway.pub_key = pub_key;
+            stored_gateway.account_id = gateway_account_id;
+
+            Gateways::<T>::insert(gateway_id, &stored_gateway);
+
+            Self::deposit_event(RawEvent::GatewayUpdated(stored_gateway));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(2)]
+        pub fn delete_gateway(origin, gateway_id: u32) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(Gateways::<T>::contains_key(gateway_id), Error::<T>::GatewayNotExists);
+            let stored_gateway = Gateways::<T>::get(gateway_id);
+
+            let farm = Farms::get(stored_gateway.farm_id);
+            ensure!(Self::is_authorized_for_twin(&account_id, farm.twin_id, types::DelegatePermission::NodeManagement), Error::<T>::FarmerNotAuthorized);
+
+            Gateways::<T>::remove(gateway_id);
+
+            let mut gateway_ids = GatewayIdsByFarmID::get(stored_gateway.farm_id);
+            gateway_ids.retain(|id| *id != gateway_id);
+            if gateway_ids.is_empty() {
+                GatewayIdsByFarmID::remove(stored_gateway.farm_id);
+            } else {
+                GatewayIdsByFarmID::insert(stored_gateway.farm_id, gateway_ids);
+            }
+
+            Self::deposit_event(RawEvent::GatewayDeleted(gateway_id));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn create_entity(origin, target: T::AccountId, name: Vec<u8>, country_id: u32, city_id: u32, signature: Vec<u8>) -> dispatch::DispatchResult {
             let _ = ensure_signed(origin)?;
 
             ensure!(!EntityIdByName::contains_key(&name), Error::<T>::EntityWithNameExists);
             ensure!(!EntityIdByAccountID::<T>::contains_key(&target), Error::<T>::EntityWithPubkeyExists);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
             
             ensure!(signature.len() == 128, Error::<T>::SignatureLenghtIsIncorrect);
             let decoded_signature_as_byteslice = <[u8; 64]>::from_hex(signature.clone()).expect("Decoding failed");
             
             let mut message = Vec::new();
             message.extend_from_slice(&name);
-            message.extend_from_slice(&country);
-            message.extend_from_slice(&city);
+            message.extend_from_slice(&country_id.to_be_bytes());
+            message.extend_from_slice(&city_id.to_be_bytes());
 
             ensure!(Self::verify_signature(decoded_signature_as_byteslice, &target, &message), Error::<T>::EntitySignatureDoesNotMatch);
 
@@ -711,8 +1190,8 @@ decl_module! {
                 version: TFGRID_ENTITY_VERSION,
                 id,
                 name: name.clone(),
-                country,
-                city,
+                country_id,
+                city_id,
                 account_id: target.clone(),
             };
 
@@ -727,10 +1206,11 @@ decl_module! {
         }
 
         #[weight = 10 + T::DbWeight::get().writes(1)]
-        pub fn update_entity(origin, name: Vec<u8>, country: Vec<u8>, city: Vec<u8>) -> dispatch::DispatchResult {
+        pub fn update_entity(origin, name: Vec<u8>, country_id: u32, city_id: u32) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
 
             ensure!(!EntityIdByName::contains_key(&name), Error::<T>::EntityWithNameExists);
+            Self::ensure_valid_country_and_city(country_id, city_id)?;
 
             ensure!(EntityIdByAccountID::<T>::contains_key(&account_id), Error::<T>::EntityNotExists);
             let stored_entity_id = EntityIdByAccountID::<T>::get(&account_id);
@@ -744,8 +1224,8 @@ decl_module! {
             EntityIdByName::remove(&stored_entity.name);
 
             stored_entity.name = name.clone();
-            stored_entity.country = country;
-            stored_entity.city = city;
+            stored_entity.country_id = country_id;
+            stored_entity.city_id = city_id;
 
             // overwrite entity
             Entities::<T>::insert(&stored_entity_id, &stored_entity);
@@ -897,6 +1377,112 @@ decl_module! {
             Ok(())
         }
 
+        // Moves the twin of the calling account to a new account, keeping the twin id and
+        // everything linked to it. The new account proves it agrees by signing
+        // (twin_id + old account + rotation nonce), the signature is passed hex encoded.
+        #[weight = 10 + T::DbWeight::get().writes(6) + T::DbWeight::get().reads(5)]
+        pub fn rotate_twin_key(origin, new_account_id: T::AccountId, signature: Vec<u8>) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
+            let twin_id = TwinIdByAccountID::<T>::get(&account_id);
+            let mut twin = Twins::<T>::get(twin_id);
+
+            // Make sure only the owner of this twin can call this method
+            ensure!(twin.account_id == account_id, Error::<T>::UnauthorizedToUpdateTwin);
+            ensure!(!TwinIdByAccountID::<T>::contains_key(&new_account_id), Error::<T>::TwinWithPubkeyExists);
+
+            ensure!(signature.len() == 128, Error::<T>::SignatureLenghtIsIncorrect);
+            let decoded_signature_as_byteslice = <[u8; 64]>::from_hex(signature)
+                .map_err(|_| Error::<T>::TwinKeySignatureDoesNotMatch)?;
+
+            let mut message = Vec::new();
+            message.extend_from_slice(&twin_id.to_be_bytes());
+            message.extend_from_slice(&account_id.encode());
+            let nonce = TwinKeyRotationNonce::get(twin_id);
+            message.extend_from_slice(&nonce.to_be_bytes());
+
+            ensure!(Self::verify_signature(decoded_signature_as_byteslice, &new_account_id, &message), Error::<T>::TwinKeySignatureDoesNotMatch);
+
+            TwinKeyRotationNonce::insert(twin_id, nonce + 1);
+
+            // Entity proofs are stored on the twin and move along with it
+            twin.account_id = new_account_id.clone();
+            Twins::<T>::insert(twin_id, &twin);
+
+            TwinIdByAccountID::<T>::remove(&account_id);
+            TwinIdByAccountID::<T>::insert(&new_account_id, twin_id);
+
+            // Move the terms and conditions accepted by the old account
+            if UsersTermsAndConditions::<T>::contains_key(&account_id) {
+                let mut users_terms_and_condition = UsersTermsAndConditions::<T>::get(&new_account_id);
+                for mut t_and_c in UsersTermsAndConditions::<T>::take(&account_id) {
+                    t_and_c.account_id = new_account_id.clone();
+                    users_terms_and_condition.push(t_and_c);
+                }
+                UsersTermsAndConditions::<T>::insert(&new_account_id, users_terms_and_condition);
+            }
+            if FarmersTermsAndConditions::<T>::contains_key(&account_id) {
+                let mut farmers_terms_and_condition = FarmersTermsAndConditions::<T>::get(&new_account_id);
+                for mut t_and_c in FarmersTermsAndConditions::<T>::take(&account_id) {
+                    t_and_c.account_id = new_account_id.clone();
+                    farmers_terms_and_condition.push(t_and_c);
+                }
+                FarmersTermsAndConditions::<T>::insert(&new_account_id, farmers_terms_and_condition);
+            }
+
+            Self::deposit_event(RawEvent::TwinAccountRotated(twin_id, account_id, new_account_id));
+            Self::deposit_event(RawEvent::TwinUpdated(twin));
+
+            Ok(())
+        }
+
+        // A twin allows another account to act on its behalf with the given permissions,
+        // granting again to the same account overrides the previous delegation
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn grant_delegation(origin, delegate: T::AccountId, permissions: Vec<types::DelegatePermission>, expires_at: Option<u64>) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
+            let twin_id = TwinIdByAccountID::<T>::get(&account_id);
+
+            ensure!(delegate != account_id, Error::<T>::CannotDelegateToSelf);
+            ensure!(!permissions.is_empty(), Error::<T>::DelegationWithoutPermissions);
+            ensure!(permissions.len() <= types::DelegatePermission::COUNT, Error::<T>::TooManyDelegatePermissions);
+            let mut permissions = permissions;
+            permissions.sort();
+            permissions.dedup();
+            if let Some(expires_at) = expires_at {
+                let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+                ensure!(expires_at > now, Error::<T>::DelegationExpiryInThePast);
+            }
+
+            let delegation = types::Delegation {
+                permissions,
+                expires_at,
+            };
+            TwinDelegations::<T>::insert(twin_id, &delegate, &delegation);
+
+            Self::deposit_event(RawEvent::DelegationGranted(twin_id, delegate, delegation));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(1)]
+        pub fn revoke_delegation(origin, delegate: T::AccountId) -> dispatch::DispatchResult {
+            let account_id = ensure_signed(origin)?;
+
+            ensure!(TwinIdByAccountID::<T>::contains_key(&account_id), Error::<T>::TwinNotExists);
+            let twin_id = TwinIdByAccountID::<T>::get(&account_id);
+
+            ensure!(TwinDelegations::<T>::contains_key(twin_id, &delegate), Error::<T>::DelegationNotExists);
+            TwinDelegations::<T>::remove(twin_id, &delegate);
+
+            Self::deposit_event(RawEvent::DelegationRevoked(twin_id, delegate));
+
+            Ok(())
+        }
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
         pub fn delete_twin(origin, twin_id: u32) -> dispatch::DispatchResult {
             let account_id = ensure_signed(origin)?;
@@ -911,12 +1497,106 @@ decl_module! {
 
             // remove twin id from this users map of twin ids
             TwinIdByAccountID::<T>::remove(&account_id.clone());
+            TwinDelegations::<T>::remove_prefix(twin_id);
 
             Self::deposit_event(RawEvent::TwinDeleted(twin_id));
 
             Ok(())
         }
 
+        #[weight = 10 + T::DbWeight::get().writes(3)]
+        pub fn create_country(origin, name: Vec<u8>, code: Vec<u8>) -> dispatch::DispatchResult {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+
+            ensure!(Self::is_valid_country_code(&code), Error::<T>::InvalidCountryCode);
+            ensure!(!CountryIdByCode::contains_key(&code), Error::<T>::CountryExists);
+
+            let id = CountryID::get() + 1;
+
+            let country = types::Country {
+                id,
+                name,
+                code: code.clone(),
+            };
+
+            Countries::insert(id, &country);
+            CountryIdByCode::insert(code, id);
+            CountryID::put(id);
+
+            Self::deposit_event(RawEvent::CountryStored(country));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(3)]
+        pub fn update_country(origin, id: u32, name: Vec<u8>, code: Vec<u8>) -> dispatch::DispatchResult {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+
+            ensure!(Countries::contains_key(id), Error::<T>::CountryNotExists);
+            ensure!(Self::is_valid_country_code(&code), Error::<T>::InvalidCountryCode);
+
+            let mut stored_country = Countries::get(id);
+            if stored_country.code != code {
+                ensure!(!CountryIdByCode::contains_key(&code), Error::<T>::CountryExists);
+                CountryIdByCode::remove(&stored_country.code);
+                CountryIdByCode::insert(&code, id);
+            }
+
+            stored_country.name = name;
+            stored_country.code = code;
+
+            Countries::insert(id, &stored_country);
+
+            Self::deposit_event(RawEvent::CountryUpdated(stored_country));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(3)]
+        pub fn create_city(origin, country_id: u32, name: Vec<u8>) -> dispatch::DispatchResult {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+
+            ensure!(Countries::contains_key(country_id), Error::<T>::CountryNotExists);
+            ensure!(!CityIdByCountryAndName::contains_key(country_id, &name), Error::<T>::CityExists);
+
+            let id = CityID::get() + 1;
+
+            let city = types::City {
+                id,
+                country_id,
+                name: name.clone(),
+            };
+
+            Cities::insert(id, &city);
+            CityIdByCountryAndName::insert(country_id, name, id);
+            CityID::put(id);
+
+            Self::deposit_event(RawEvent::CityStored(city));
+
+            Ok(())
+        }
+
+        #[weight = 10 + T::DbWeight::get().writes(3)]
+        pub fn update_city(origin, id: u32, name: Vec<u8>) -> dispatch::DispatchResult {
+            T::RestrictedOrigin::ensure_origin(origin)?;
+
+            ensure!(Cities::contains_key(id), Error::<T>::CityNotExists);
+            let mut stored_city = Cities::get(id);
+
+            if stored_city.name != name {
+                ensure!(!CityIdByCountryAndName::contains_key(stored_city.country_id, &name), Error::<T>::CityExists);
+                CityIdByCountryAndName::remove(stored_city.country_id, &stored_city.name);
+                CityIdByCountryAndName::insert(stored_city.country_id, &name, id);
+            }
+
+            stored_city.name = name;
+            Cities::insert(id, &stored_city);
+
+            Self::deposit_event(RawEvent::CityUpdated(stored_city));
+
+            Ok(())
+        }
+
         #[weight = 10 + T::DbWeight::get().writes(1)]
         pub fn create_pricing_policy(
             origin,
@@ -1125,6 +1805,386 @@ impl<T: Config> Module<T> {
         sp_core::sr25519::Public::from_raw(bytes)
     }
 
+    fn ensure_valid_country_and_city(country_id: u32, city_id: u32) -> dispatch::DispatchResult {
+        ensure!(Countries::contains_key(country_id), Error::<T>::CountryNotExists);
+        ensure!(Cities::contains_key(city_id), Error::<T>::CityNotExists);
+        ensure!(Cities::get(city_id).country_id == country_id, Error::<T>::CityNotInCountry);
+
+        Ok(())
+    }
+
+    fn parse_location(location: &types::Location) -> Result<types::Coordinates, dispatch::DispatchError> {
+        let latitude = location.parse_latitude().ok_or(Error::<T>::InvalidLatitude)?;
+        let longitude = location.parse_longitude().ok_or(Error::<T>::InvalidLongitude)?;
+
+        Ok(types::Coordinates {
+            latitude,
+            longitude,
+        })
+    }
+
+    // Validates a public ip that is added next to the given public ips of a farm. Existing ips
+    // that can't be parsed (stored before validation was introduced) are only checked on equality.
+    fn ensure_can_add_public_ip(public_ips: &[types::PublicIP], ip: &types::PublicIP) -> dispatch::DispatchResult {
+        let cidr = types::Ipv4Cidr::parse(&ip.ip).ok_or(Error::<T>::InvalidPublicIP)?;
+        let gateway = types::parse_ipv4(&ip.gateway).ok_or(Error::<T>::InvalidGatewayIP)?;
+
+        ensure!((public_ips.len() as u32) < MAX_FARM_PUBLIC_IPS, Error::<T>::TooManyFarmPublicIPs);
+        ensure!(!types::is_reserved_ipv4(cidr.address), Error::<T>::PublicIPIsReserved);
+        ensure!(cidr.prefix >= MIN_PUBLIC_IP_PREFIX, Error::<T>::PublicIPPrefixTooShort);
+        ensure!(
+            cidr.address != cidr.first() && cidr.address != cidr.last(),
+            Error::<T>::PublicIPIsNetworkOrBroadcastAddress
+        );
+        ensure!(cidr.contains(gateway), Error::<T>::GatewayIPNotInSubnet);
+        ensure!(gateway != cidr.address, Error::<T>::GatewayIPIsPublicIP);
+
+        for public_ip in public_ips {
+            ensure!(public_ip.ip != ip.ip, Error::<T>::IpExists);
+            if let Some(existing) = types::Ipv4Cidr::parse(&public_ip.ip) {
+                ensure!(existing.address != cidr.address, Error::<T>::IpExists);
+                ensure!(existing.address != gateway, Error::<T>::GatewayIPIsPublicIP);
+            }
+            if let Some(existing_gateway) = types::parse_ipv4(&public_ip.gateway) {
+                ensure!(existing_gateway != cidr.address, Error::<T>::GatewayIPIsPublicIP);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Weight of adding or removing a range of ips on a farm, every ip of the range is checked
+    // against the ips that are on the farm, which grow with the range while adding
+    fn ip_range_weight(count: u32, farm_ips: u32) -> Weight {
+        let count = Weight::from(count.min(MAX_IP_RANGE_SIZE));
+        let farm_ips = Weight::from(farm_ips.min(MAX_FARM_PUBLIC_IPS));
+
+        (10 as Weight)
+            .saturating_add(T::DbWeight::get().reads_writes(3, 1))
+            .saturating_add(count.saturating_mul(farm_ips.saturating_add(count)).saturating_mul(IP_CHECK_WEIGHT))
+    }
+
+    // Returns the `count` consecutive addresses starting from the given cidr, all with its prefix
+    fn get_ip_range(start_ip: &[u8], count: u32) -> Result<Vec<types::Ipv4Cidr>, dispatch::DispatchError> {
+        let start = types::Ipv4Cidr::parse(start_ip).ok_or(Error::<T>::InvalidPublicIP)?;
+        ensure!(count > 0 && count <= MAX_IP_RANGE_SIZE, Error::<T>::InvalidIPRange);
+        ensure!(start.address.checked_add(count - 1).is_some(), Error::<T>::InvalidIPRange);
+
+        Ok((0..count).map(|i| types::Ipv4Cidr {
+            address: start.address + i,
+            prefix: start.prefix,
+        }).collect())
+    }
+
+    // Returns the ids of the nodes located within the bounding box, coordinates are in millionths
+    // of a degree. A box crossing the antimeridian can be queried with min_longitude > max_longitude.
+    pub fn nodes_in_bounding_box(min_latitude: i32, min_longitude: i32, max_latitude: i32, max_longitude: i32) -> Vec<u32> {
+        Nodes::iter()
+            .filter(|(_, node)| {
+                let latitude = node.location.latitude;
+                let longitude = node.location.longitude;
+
+                let in_latitude = latitude >= min_latitude && latitude <= max_latitude;
+                let in_longitude = if min_longitude <= max_longitude {
+                    longitude >= min_longitude && longitude <= max_longitude
+                } else {
+                    longitude >= min_longitude || longitude <= max_longitude
+                };
+
+                in_latitude && in_longitude
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    // Country codes are ISO 3166-1 alpha-2 codes (two uppercase letters)
+    fn is_valid_country_code(code: &[u8]) -> bool {
+        code.len() == 2 && code.iter().all(|c| c.is_ascii_uppercase())
+    }
+
+    // Storage migration, V1Struct to V3Struct all hold the original layout. In V4Struct:
+    // - nodes and entities reference countries and cities by id instead of by name. The registry
+    //   is seeded with the names found in storage, countries created this way have no code yet.
+    //   Empty names map to id 0. Existing nodes are marked up.
+    // - node locations are stored as numeric coordinates, locations that cannot be parsed
+    //   are reset to 0, 0.
+    // - farms get an empty list of public ipv6 prefixes.
+    pub fn migrate_storage() -> Weight {
+        let version = PalletVersion::get();
+        if version == types::StorageVersion::V4Struct {
+            return 0;
+        }
+
+        debug::info!(" >>> Migrating tfgrid storage from {:?} to V4Struct", version);
+
+        let mut reads_writes = 0;
+
+        let mut country_ids: BTreeMap<Vec<u8>, u32> = BTreeMap::new();
+        for (id, country) in Countries::iter() {
+            country_ids.insert(country.name, id);
+            reads_writes += 1;
+        }
+
+        // nodes from before availability tracking are running, they are up until
+        // they miss the heartbeat window
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+
+        Nodes::translate::<types::deprecated::NodeV3, _>(|id, node| {
+            let (country_id, city_id) = Self::get_or_create_country_and_city(&mut country_ids, node.country, node.city);
+            NodeAvailabilityByID::insert(id, types::NodeAvailability {
+                status: types::NodeStatus::Up,
+                last_seen: now,
+            });
+            reads_writes += 1;
+
+            Some(types::Node {
+                version: TFGRID_NODE_VERSION,
+                id: node.id,
+                farm_id: node.farm_id,
+                twin_id: node.twin_id,
+                resources: node.resources,
+                location: Self::get_coordinates_or_default(&node.location),
+                country_id,
+                city_id,
+                public_config: node.public_config,
+                created: node.created,
+                farming_policy_id: node.farming_policy_id,
+                interfaces: node.interfaces,
+                certification_type: node.certification_type,
+            })
+        });
+
+        Entities::<T>::translate::<types::deprecated::EntityV1<T::AccountId>, _>(|_, entity| {
+            let (country_id, city_id) = Self::get_or_create_country_and_city(&mut country_ids, entity.country, entity.city);
+            reads_writes += 1;
+
+            Some(types::Entity {
+                version: TFGRID_ENTITY_VERSION,
+                id: entity.id,
+                name: entity.name,
+                account_id: entity.account_id,
+                country_id,
+                city_id,
+            })
+        });
+
+        Farms::translate::<types::deprecated::FarmV1, _>(|_, farm| {
+            reads_writes += 1;
+
+            Some(types::Farm {
+                version: TFGRID_FARM_VERSION,
+                id: farm.id,
+                name: farm.name,
+                twin_id: farm.twin_id,
+                pricing_policy_id: farm.pricing_policy_id,
+                certification_type: farm.certification_type,
+                public_ips: farm.public_ips,
+                public_ipv6_prefixes: Vec::new(),
+            })
+        });
+
+        PalletVersion::put(types::StorageVersion::V4Struct);
+
+        // every translated value is read and written, creating countries and cities writes as well
+        T::DbWeight::get().reads_writes(reads_writes + 1, reads_writes * 4 + 1)
+    }
+
+    fn get_coordinates_or_default(location: &types::Location) -> types::Coordinates {
+        match (location.parse_latitude(), location.parse_longitude()) {
+            (Some(latitude), Some(longitude)) => types::Coordinates {
+                latitude,
+                longitude,
+            },
+            _ => types::Coordinates::default(),
+        }
+    }
+
+    fn get_or_create_country_and_city(country_ids: &mut BTreeMap<Vec<u8>, u32>, country: Vec<u8>, city: Vec<u8>) -> (u32, u32) {
+        if country.is_empty() {
+            return (0, 0);
+        }
+
+        let country_id = match country_ids.get(&country) {
+            Some(id) => *id,
+            None => {
+                let id = CountryID::get() + 1;
+                Countries::insert(id, types::Country {
+                    id,
+                    name: country.clone(),
+                    code: Vec::new(),
+                });
+                CountryID::put(id);
+                country_ids.insert(country, id);
+                id
+            }
+        };
+
+        if city.is_empty() {
+            return (country_id, 0);
+        }
+
+        if CityIdByCountryAndName::contains_key(country_id, &city) {
+            return (country_id, CityIdByCountryAndName::get(country_id, &city));
+        }
+
+        let city_id = CityID::get() + 1;
+        Cities::insert(city_id, types::City {
+            id: city_id,
+            country_id,
+            name: city.clone(),
+        });
+        CityIdByCountryAndName::insert(country_id, city, city_id);
+        CityID::put(city_id);
+
+        (country_id, city_id)
+    }
+
+    // Returns true if the account owns the twin, or holds a delegation of the twin that
+    // did not expire and contains the requested permission
+    pub fn is_authorized_for_twin(account_id: &T::AccountId, twin_id: u32, permission: types::DelegatePermission) -> bool {
+        if !Twins::<T>::contains_key(twin_id) {
+            return false;
+        }
+
+        if &Twins::<T>::get(twin_id).account_id == account_id {
+            return true;
+        }
+
+        if !TwinDelegations::<T>::contains_key(twin_id, account_id) {
+            return false;
+        }
+
+        let delegation = TwinDelegations::<T>::get(twin_id, account_id);
+        if let Some(expires_at) = delegation.expires_at {
+            let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+            if now >= expires_at {
+                return false;
+            }
+        }
+
+        delegation.permissions.contains(&permission)
+    }
+
+    // Returns the status of a node as seen by the grid, a node that is up but did not
+    // report its uptime within the heartbeat window is considered down
+    pub fn get_node_status(node_id: u32) -> types::NodeStatus {
+        let availability = NodeAvailabilityByID::get(node_id);
+        if availability.status != types::NodeStatus::Up {
+            return availability.status;
+        }
+
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+        if now.saturating_sub(availability.last_seen) > T::HeartbeatWindow::get() {
+            return types::NodeStatus::Down;
+        }
+
+        types::NodeStatus::Up
+    }
+
+    // Accounts the uptime of a node since its previous report into its uptime history.
+    // The credited uptime is capped to the time that elapsed on chain, so a node
+    // with a skewed clock cannot report more uptime than actually passed.
+    fn account_node_uptime(node_id: u32, now: u64, uptime: u64) {
+        let mut tracker = NodeUptimeTrackerByID::get(node_id);
+        let first_report = tracker.last_report == 0;
+        let elapsed = now.saturating_sub(tracker.last_report);
+
+        let mut rebooted = false;
+        let credited = if first_report {
+            // the first report only anchors the tracker
+            0
+        } else if uptime < tracker.last_uptime {
+            // uptime went backwards, the node rebooted since its previous report
+            // and was up for `uptime` seconds since
+            rebooted = true;
+            uptime.min(elapsed)
+        } else {
+            (uptime - tracker.last_uptime).min(elapsed)
+        };
+
+        tracker.last_uptime = uptime;
+        tracker.last_report = now;
+        NodeUptimeTrackerByID::insert(node_id, &tracker);
+
+        if credited == 0 && !rebooted {
+            return;
+        }
+
+        let period_length = T::UptimePeriod::get();
+        if period_length == 0 {
+            return;
+        }
+        let mut history = NodeUptimeHistoryByID::get(node_id);
+
+        // split the credited uptime over the periods it spans, only the periods
+        // that are kept in the history are accounted
+        let mut start = (now - credited).max(now.saturating_sub(MAX_UPTIME_PERIODS as u64 * period_length));
+        while start < now {
+            let period = start / period_length;
+            let period_end = (period + 1) * period_length;
+            let end = now.min(period_end);
+            Self::add_uptime_to_history(&mut history, period, end - start, 0);
+            start = end;
+        }
+
+        if rebooted {
+            Self::add_uptime_to_history(&mut history, now / period_length, 0, 1);
+            Self::deposit_event(RawEvent::NodeRebooted(node_id, uptime));
+        }
+
+        // only keep the most recent periods
+        if history.len() > MAX_UPTIME_PERIODS {
+            let excess = history.len() - MAX_UPTIME_PERIODS;
+            history.drain(..excess);
+        }
+
+        NodeUptimeHistoryByID::insert(node_id, &history);
+    }
+
+    fn add_uptime_to_history(history: &mut Vec<types::UptimeBucket>, period: u64, uptime: u64, reboots: u32) {
+        match history.iter_mut().find(|bucket| bucket.period == period) {
+            Some(bucket) => {
+                bucket.uptime += uptime;
+                bucket.reboots += reboots;
+            }
+            None => {
+                // reports come in chronologically, so a new period is always the latest
+                history.push(types::UptimeBucket {
+                    period,
+                    uptime,
+                    reboots,
+                });
+            }
+        }
+    }
+
+    // Returns the availability of a node during an uptime period, as the share of the
+    // period (limited to the part the node existed in and that has already passed)
+    // the node was up for
+    pub fn get_node_availability(node_id: u32, period: u64) -> Perbill {
+        if !Nodes::contains_key(node_id) {
+            return Perbill::zero();
+        }
+        let node = Nodes::get(node_id);
+
+        let period_length = T::UptimePeriod::get();
+        let now = <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000;
+
+        let start = (period * period_length).max(node.created);
+        let end = ((period + 1) * period_length).min(now);
+        if end <= start {
+            return Perbill::zero();
+        }
+
+        let uptime = NodeUptimeHistoryByID::get(node_id)
+            .iter()
+            .find(|bucket| bucket.period == period)
+            .map(|bucket| bucket.uptime)
+            .unwrap_or(0);
+
+        Perbill::from_rational_approximation(uptime.min(end - start), end - start)
+    }
+
     pub fn fund_node_wallet(node_id: u32) {
         if !Nodes::contains_key(&node_id) {
             return;
@@ -5,7 +5,7 @@ use sp_runtime::{
     testing::Header,
     traits::{BlakeTwo256, IdentityLookup},
 };
-use frame_system::EnsureRoot;
+use frame_system::{EnsureRoot, RawOrigin};
 
 use sp_core::{ed25519, sr25519, Pair, Public, H256};
 
@@ -15,6 +15,7 @@ use sp_runtime::traits::{IdentifyAccount, Verify};
 use sp_runtime::MultiSignature;
 
 use hex;
+use codec::Encode;
 
 pub type Signature = MultiSignature;
 
@@ -42,6 +43,8 @@ parameter_types! {
     pub BlockWeights: frame_system::limits::BlockWeights =
         frame_system::limits::BlockWeights::simple_max(1024);
     pub const ExistentialDeposit: u64 = 1;
+    pub const HeartbeatWindow: u64 = 7200;
+    pub const UptimePeriod: u64 = 3600;
 }
 
 impl frame_system::Config for TestRuntime {
@@ -73,6 +76,8 @@ impl Config for TestRuntime {
     type Event = Event;
     type Currency = Balances;
     type RestrictedOrigin = EnsureRoot<Self::AccountId>;
+    type HeartbeatWindow = HeartbeatWindow;
+    type UptimePeriod = UptimePeriod;
 }
 
 impl pallet_balances::Config for TestRuntime {
@@ -100,7 +105,10 @@ impl ExternalityBuilder {
             .build_storage::<TestRuntime>()
             .unwrap();
         let mut ext = TestExternalities::from(storage);
-        ext.execute_with(|| System::set_block_number(1));
+        ext.execute_with(|| {
+            System::set_block_number(1);
+            create_country_and_city();
+        });
         ext
     }
 }
@@ -114,7 +122,20 @@ pub fn new_test_ext() -> sp_io::TestExternalities {
         balances: vec![(alice(), 1000000000000), (bob(), 190000)],
     };
     genesis.assimilate_storage(&mut t).unwrap();
-    t.into()
+    let mut ext = sp_io::TestExternalities::from(t);
+    ext.execute_with(create_country_and_city);
+    ext
+}
+
+// registers Belgium (1) and Ghent (1) which are used by the tests
+fn create_country_and_city() {
+    TfgridModule::create_country(
+        RawOrigin::Root.into(),
+        "Belgium".as_bytes().to_vec(),
+        "BE".as_bytes().to_vec(),
+    )
+    .unwrap();
+    TfgridModule::create_city(RawOrigin::Root.into(), 1, "Ghent".as_bytes().to_vec()).unwrap();
 }
 
 // industry dismiss casual gym gap music pave gasp sick owner dumb cost
@@ -166,15 +187,15 @@ pub fn bob() -> AccountId {
     get_account_id_from_seed::<sr25519::Public>("Bob")
 }
 
-pub fn sign_create_entity(name: Vec<u8>, country: Vec<u8>, city: Vec<u8>) -> Vec<u8> {
+pub fn sign_create_entity(name: Vec<u8>, country_id: u32, city_id: u32) -> Vec<u8> {
     let seed =
         hex::decode("59336423ee7af732b2d4a76e440651e33e5ba51540e5633535b9030492c2a6f6").unwrap();
     let pair = ed25519::Pair::from_seed_slice(&seed).unwrap();
 
     let mut message = vec![];
     message.extend_from_slice(&name);
-    message.extend_from_slice(&country);
-    message.extend_from_slice(&city);
+    message.extend_from_slice(&country_id.to_be_bytes());
+    message.extend_from_slice(&city_id.to_be_bytes());
 
     let signature = pair.sign(&message);
 
@@ -182,15 +203,15 @@ pub fn sign_create_entity(name: Vec<u8>, country: Vec<u8>, city: Vec<u8>) -> Vec
     hex::encode(signature.0.to_vec()).into()
 }
 
-pub fn sign_create_entity_sr(name: Vec<u8>, country: Vec<u8>, city: Vec<u8>) -> Vec<u8> {
+pub fn sign_create_entity_sr(name: Vec<u8>, country_id: u32, city_id: u32) -> Vec<u8> {
     let seed =
         hex::decode("59336423ee7af732b2d4a76e440651e33e5ba51540e5633535b9030492c2a6f6").unwrap();
     let pair = sr25519::Pair::from_seed_slice(&seed).unwrap();
 
     let mut message = vec![];
     message.extend_from_slice(&name);
-    message.extend_from_slice(&country);
-    message.extend_from_slice(&city);
+    message.extend_from_slice(&country_id.to_be_bytes());
+    message.extend_from_slice(&city_id.to_be_bytes());
 
     let signature = pair.sign(&message);
 
@@ -212,3 +233,33 @@ pub fn sign_add_entity_to_twin(entity_id: u32, twin_id: u32) -> Vec<u8> {
     // hex encode signature
     hex::encode(signature.0.to_vec()).into()
 }
+
+fn rotate_twin_key_message(twin_id: u32, old_account_id: AccountId, nonce: u64) -> Vec<u8> {
+    let mut message = vec![];
+    message.extend_from_slice(&twin_id.to_be_bytes());
+    message.extend_from_slice(&old_account_id.encode());
+    message.extend_from_slice(&nonce.to_be_bytes());
+    message
+}
+
+// signed by the test_ed25519 account
+pub fn sign_rotate_twin_key(twin_id: u32, old_account_id: AccountId, nonce: u64) -> Vec<u8> {
+    let seed =
+        hex::decode("59336423ee7af732b2d4a76e440651e33e5ba51540e5633535b9030492c2a6f6").unwrap();
+    let pair = ed25519::Pair::from_seed_slice(&seed).unwrap();
+
+    let signature = pair.sign(&rotate_twin_key_message(twin_id, old_account_id, nonce));
+
+    // hex encode signature
+    hex::encode(signature.0).into()
+}
+
+// signed by the alice account
+pub fn sign_rotate_twin_key_alice(twin_id: u32, old_account_id: AccountId, nonce: u64) -> Vec<u8> {
+    let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+
+    let signature = pair.sign(&rotate_twin_key_message(twin_id, old_account_id, nonce));
+
+    // hex encode signature
+    hex::encode(signature.0).into()
+}
@@ -1,7 +1,14 @@
 use crate::{mock::*, Error};
-use frame_support::{assert_noop, assert_ok};
+use codec::Encode;
+use frame_support::{
+    assert_noop, assert_ok,
+    storage::migration::put_storage_value,
+    traits::OnRuntimeUpgrade,
+    weights::GetDispatchInfo,
+    Blake2_128Concat, StorageHasher, StorageMap,
+};
 use frame_system::RawOrigin;
-use sp_runtime::traits::SaturatedConversion;
+use sp_runtime::{traits::SaturatedConversion, Perbill};
 
 #[test]
 fn test_create_entity_works() {
@@ -23,16 +30,16 @@ fn test_update_entity_works() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
 
-        let country = "Belgium".as_bytes().to_vec();
-        let city = "Ghent".as_bytes().to_vec();
+        let country_id = 1;
+        let city_id = 1;
         // Change name to barfoo
         let name = "barfoo".as_bytes().to_vec();
 
         assert_ok!(TfgridModule::update_entity(
             Origin::signed(test_ed25519()),
             name,
-            country,
-            city
+            country_id,
+            city_id
         ));
     });
 }
@@ -42,13 +49,13 @@ fn test_update_entity_fails_if_signed_by_someone_else() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
 
-        let country = "Belgium".as_bytes().to_vec();
-        let city = "Ghent".as_bytes().to_vec();
+        let country_id = 1;
+        let city_id = 1;
         // Change name to barfoo
         let name = "barfoo".as_bytes().to_vec();
 
         assert_noop!(
-            TfgridModule::update_entity(Origin::signed(bob()), name, country, city),
+            TfgridModule::update_entity(Origin::signed(bob()), name, country_id, city_id),
             Error::<TestRuntime>::EntityNotExists
         );
     });
@@ -60,17 +67,17 @@ fn test_create_entity_double_fails() {
         create_entity();
 
         let name = "foobar".as_bytes().to_vec();
-        let country = "Belgium".as_bytes().to_vec();
-        let city = "Ghent".as_bytes().to_vec();
-        let signature = sign_create_entity(name.clone(), country.clone(), city.clone());
+        let country_id = 1;
+        let city_id = 1;
+        let signature = sign_create_entity(name.clone(), country_id, city_id);
 
         assert_noop!(
             TfgridModule::create_entity(
                 Origin::signed(alice()),
                 test_ed25519(),
                 name,
-                country,
-                city,
+                country_id,
+                city_id,
                 signature
             ),
             Error::<TestRuntime>::EntityWithNameExists
@@ -84,18 +91,18 @@ fn test_create_entity_double_fails_with_same_pubkey() {
         create_entity();
 
         let name = "barfoo".as_bytes().to_vec();
-        let country = "Belgium".as_bytes().to_vec();
-        let city = "Ghent".as_bytes().to_vec();
+        let country_id = 1;
+        let city_id = 1;
 
-        let signature = sign_create_entity(name.clone(), country.clone(), city.clone());
+        let signature = sign_create_entity(name.clone(), country_id, city_id);
 
         assert_noop!(
             TfgridModule::create_entity(
                 Origin::signed(alice()),
                 test_ed25519(),
                 name,
-                country,
-                city,
+                country_id,
+                city_id,
                 signature
             ),
             Error::<TestRuntime>::EntityWithPubkeyExists
@@ -275,6 +282,249 @@ fn test_create_twin_double_fails() {
     });
 }
 
+#[test]
+fn test_rotate_twin_key_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let signature = sign_add_entity_to_twin(1, 1);
+        assert_ok!(TfgridModule::add_twin_entity(
+            Origin::signed(alice()),
+            1,
+            1,
+            signature
+        ));
+
+        let signature = sign_rotate_twin_key(1, alice(), 0);
+        assert_ok!(TfgridModule::rotate_twin_key(
+            Origin::signed(alice()),
+            test_ed25519(),
+            signature
+        ));
+
+        let twin = TfgridModule::twins(1);
+        assert_eq!(twin.account_id, test_ed25519());
+        assert_eq!(twin.entities.len(), 1);
+        assert_eq!(TfgridModule::twin_ids_by_pubkey(test_ed25519()), 1);
+        assert_eq!(TfgridModule::twin_ids_by_pubkey(alice()), 0);
+
+        let users_terms_and_condition = TfgridModule::users_terms_and_condition(test_ed25519());
+        assert_eq!(users_terms_and_condition.len(), 1);
+        assert_eq!(users_terms_and_condition[0].account_id, test_ed25519());
+        assert_eq!(TfgridModule::users_terms_and_condition(alice()).len(), 0);
+        assert_eq!(TfgridModule::farmers_terms_and_condition(test_ed25519()).len(), 1);
+
+        // the farm linked to the twin is now managed with the new key
+        assert_noop!(
+            TfgridModule::update_farm(Origin::signed(alice()), 1, "new_name".as_bytes().to_vec(), 1),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+        assert_ok!(TfgridModule::update_farm(
+            Origin::signed(test_ed25519()),
+            1,
+            "new_name".as_bytes().to_vec(),
+            1
+        ));
+    });
+}
+
+#[test]
+fn test_rotate_twin_key_with_wrong_signature_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_twin();
+        create_twin_bob();
+
+        // signed for another twin
+        let signature = sign_rotate_twin_key(2, alice(), 0);
+        assert_noop!(
+            TfgridModule::rotate_twin_key(Origin::signed(alice()), test_ed25519(), signature),
+            Error::<TestRuntime>::TwinKeySignatureDoesNotMatch
+        );
+
+        // the new account already has a twin
+        let signature = sign_rotate_twin_key(1, alice(), 0);
+        assert_noop!(
+            TfgridModule::rotate_twin_key(Origin::signed(alice()), bob(), signature),
+            Error::<TestRuntime>::TwinWithPubkeyExists
+        );
+    });
+}
+
+#[test]
+fn test_rotate_twin_key_cannot_be_replayed() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_twin();
+
+        let signature = sign_rotate_twin_key(1, alice(), 0);
+        assert_ok!(TfgridModule::rotate_twin_key(
+            Origin::signed(alice()),
+            test_ed25519(),
+            signature.clone()
+        ));
+        assert_eq!(TfgridModule::twin_key_rotation_nonce(1), 1);
+
+        // rotate back to the original key
+        assert_ok!(TfgridModule::rotate_twin_key(
+            Origin::signed(test_ed25519()),
+            alice(),
+            sign_rotate_twin_key_alice(1, test_ed25519(), 1)
+        ));
+
+        assert_noop!(
+            TfgridModule::rotate_twin_key(Origin::signed(alice()), test_ed25519(), signature),
+            Error::<TestRuntime>::TwinKeySignatureDoesNotMatch
+        );
+        assert_ok!(TfgridModule::rotate_twin_key(
+            Origin::signed(alice()),
+            test_ed25519(),
+            sign_rotate_twin_key(1, alice(), 2)
+        ));
+    });
+}
+
+#[test]
+fn test_delegated_farm_management_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        assert_noop!(
+            TfgridModule::add_farm_ip(
+                Origin::signed(bob()),
+                1,
+                "185.206.122.34/24".as_bytes().to_vec(),
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+
+        assert_ok!(TfgridModule::grant_delegation(
+            Origin::signed(alice()),
+            bob(),
+            vec![super::types::DelegatePermission::FarmManagement],
+            None
+        ));
+
+        // bob does not need a twin to act on behalf of alice's twin
+        assert_ok!(TfgridModule::add_farm_ip(
+            Origin::signed(bob()),
+            1,
+            "185.206.122.34/24".as_bytes().to_vec(),
+            "185.206.122.1".as_bytes().to_vec()
+        ));
+        assert_ok!(TfgridModule::update_farm(
+            Origin::signed(bob()),
+            1,
+            "new_name".as_bytes().to_vec(),
+            1
+        ));
+
+        // node management was not delegated
+        assert_noop!(
+            TfgridModule::set_node_standby(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmerNotAuthorized
+        );
+
+        assert_ok!(TfgridModule::revoke_delegation(Origin::signed(alice()), bob()));
+        assert_noop!(
+            TfgridModule::remove_farm_ip(Origin::signed(bob()), 1, "185.206.122.34/24".as_bytes().to_vec()),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+    });
+}
+
+#[test]
+fn test_delegation_expires() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        assert_ok!(TfgridModule::grant_delegation(
+            Origin::signed(alice()),
+            bob(),
+            vec![super::types::DelegatePermission::NodeManagement],
+            Some(1628082000 + 100)
+        ));
+        assert_ok!(TfgridModule::set_node_standby(Origin::signed(bob()), 1));
+
+        Timestamp::set_timestamp((1628082000 + 100) * 1000);
+        assert_noop!(
+            TfgridModule::decommission_node(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmerNotAuthorized
+        );
+    });
+}
+
+#[test]
+fn test_grant_delegation_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_twin();
+        Timestamp::set_timestamp(1628082000 * 1000);
+
+        assert_noop!(
+            TfgridModule::grant_delegation(Origin::signed(alice()), bob(), Vec::new(), None),
+            Error::<TestRuntime>::DelegationWithoutPermissions
+        );
+        assert_noop!(
+            TfgridModule::grant_delegation(
+                Origin::signed(alice()),
+                alice(),
+                vec![super::types::DelegatePermission::Contracts],
+                None
+            ),
+            Error::<TestRuntime>::CannotDelegateToSelf
+        );
+        assert_noop!(
+            TfgridModule::grant_delegation(
+                Origin::signed(alice()),
+                bob(),
+                vec![super::types::DelegatePermission::Contracts],
+                Some(1628082000)
+            ),
+            Error::<TestRuntime>::DelegationExpiryInThePast
+        );
+        assert_noop!(
+            TfgridModule::grant_delegation(
+                Origin::signed(alice()),
+                bob(),
+                vec![super::types::DelegatePermission::Contracts; 4],
+                None
+            ),
+            Error::<TestRuntime>::TooManyDelegatePermissions
+        );
+        assert_noop!(
+            TfgridModule::revoke_delegation(Origin::signed(alice()), bob()),
+            Error::<TestRuntime>::DelegationNotExists
+        );
+
+        // duplicate permissions are only stored once
+        assert_ok!(TfgridModule::grant_delegation(
+            Origin::signed(alice()),
+            bob(),
+            vec![
+                super::types::DelegatePermission::NodeManagement,
+                super::types::DelegatePermission::Contracts,
+                super::types::DelegatePermission::NodeManagement,
+            ],
+            None
+        ));
+        assert_eq!(
+            TfgridModule::twin_delegations(1, bob()).permissions,
+            vec![
+                super::types::DelegatePermission::Contracts,
+                super::types::DelegatePermission::NodeManagement,
+            ]
+        );
+    });
+}
+
 #[test]
 fn test_create_farm_works() {
     ExternalityBuilder::build().execute_with(|| {
@@ -293,13 +543,13 @@ fn test_create_farm_with_double_ip_fails() {
         let farm_name = "test_farm".as_bytes().to_vec();
         let mut pub_ips = Vec::new();
         pub_ips.push(super::types::PublicIP {
-            ip: "1.1.1.0".as_bytes().to_vec(),
-            gateway: "1.1.1.1".as_bytes().to_vec(),
+            ip: "185.206.122.33/24".as_bytes().to_vec(),
+            gateway: "185.206.122.1".as_bytes().to_vec(),
             contract_id: 0,
         });
         pub_ips.push(super::types::PublicIP {
-            ip: "1.1.1.0".as_bytes().to_vec(),
-            gateway: "1.1.1.1".as_bytes().to_vec(),
+            ip: "185.206.122.33/24".as_bytes().to_vec(),
+            gateway: "185.206.122.1".as_bytes().to_vec(),
             contract_id: 0,
         });
 
@@ -329,9 +579,199 @@ fn test_adding_ip_to_farm_works() {
         assert_ok!(TfgridModule::add_farm_ip(
             Origin::signed(alice()),
             1,
-            "1.1.1.2".as_bytes().to_vec(),
-            "1.1.1.1".as_bytes().to_vec()
+            "185.206.122.34/24".as_bytes().to_vec(),
+            "185.206.122.1".as_bytes().to_vec()
+        ));
+    });
+}
+
+#[test]
+fn test_removing_reserved_ip_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        // reserve the ip of the farm for contract 1
+        let mut farm = TfgridModule::farms(1);
+        farm.public_ips[0].contract_id = 1;
+        super::Farms::insert(1, &farm);
+
+        assert_noop!(
+            TfgridModule::remove_farm_ip(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.33/24".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::CannotRemoveReservedIP
+        );
+        assert_noop!(
+            TfgridModule::remove_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.33/24".as_bytes().to_vec(),
+                1
+            ),
+            Error::<TestRuntime>::CannotRemoveReservedIP
+        );
+    });
+}
+
+#[test]
+fn test_retiring_free_ip_removes_it() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        assert_noop!(
+            TfgridModule::retire_farm_ip(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.34/24".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::IpNotExists
+        );
+
+        assert_ok!(TfgridModule::retire_farm_ip(
+            Origin::signed(alice()),
+            1,
+            "185.206.122.33/24".as_bytes().to_vec()
+        ));
+        assert_eq!(TfgridModule::farms(1).public_ips.len(), 0);
+        assert!(!TfgridModule::farm_ips_retiring(1, "185.206.122.33/24".as_bytes().to_vec()));
+    });
+}
+
+#[test]
+fn test_adding_ipv6_prefix_to_farm_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        assert_ok!(TfgridModule::add_farm_ipv6_prefix(
+            Origin::signed(alice()),
+            1,
+            "2a02:1802:005e:0000::/48".as_bytes().to_vec(),
+            "2A02:1802:5E::1".as_bytes().to_vec()
+        ));
+
+        // prefixes are stored in their canonical form
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ipv6_prefixes.len(), 1);
+        assert_eq!(farm.public_ipv6_prefixes[0].prefix, "2a02:1802:5e::/48".as_bytes().to_vec());
+        assert_eq!(farm.public_ipv6_prefixes[0].gateway, "2a02:1802:5e::1".as_bytes().to_vec());
+
+        let cases = vec![
+            ("2a02:1802:5e::", "2a02:1802:5e::1", Error::<TestRuntime>::InvalidPublicIPv6Prefix),
+            ("2a02:1802:5f::1/48", "2a02:1802:5f::2", Error::<TestRuntime>::InvalidPublicIPv6Prefix),
+            ("2a02:1802:5f:1:2::/80", "2a02:1802:5f:1:2::1", Error::<TestRuntime>::InvalidPublicIPv6Prefix),
+            ("2a02:1802:5f::/48", "2a02:1802:5f:::1", Error::<TestRuntime>::InvalidGatewayIPv6),
+            ("2a02:1802:5f::/48", "2a02:1802:60::1", Error::<TestRuntime>::GatewayIPv6NotInPrefix),
+            ("fd00:1::/48", "fd00:1::1", Error::<TestRuntime>::PublicIPv6PrefixIsReserved),
+            ("2001:db8:1::/48", "2001:db8:1::1", Error::<TestRuntime>::PublicIPv6PrefixIsReserved),
+            ("2a02:1802:5e:10::/64", "2a02:1802:5e:10::1", Error::<TestRuntime>::IpExists),
+            ("2a02:1800::/24", "2a02:1800::1", Error::<TestRuntime>::IpExists),
+        ];
+
+        for (prefix, gateway, error) in cases {
+            assert_noop!(
+                TfgridModule::add_farm_ipv6_prefix(
+                    Origin::signed(alice()),
+                    1,
+                    prefix.as_bytes().to_vec(),
+                    gateway.as_bytes().to_vec()
+                ),
+                error
+            );
+        }
+
+        assert_ok!(TfgridModule::remove_farm_ipv6_prefix(
+            Origin::signed(alice()),
+            1,
+            "2a02:1802:5e::/48".as_bytes().to_vec()
+        ));
+        assert_eq!(TfgridModule::farms(1).public_ipv6_prefixes.len(), 0);
+    });
+}
+
+#[test]
+fn test_ipv6_prefix_allocation_works() {
+    let mut prefix = super::types::PublicIPv6Prefix {
+        prefix: "2a02:1802:5e::/62".as_bytes().to_vec(),
+        gateway: "2a02:1802:5e::1".as_bytes().to_vec(),
+        allocations: Vec::new(),
+    };
+
+    // the first /64 holds the gateway
+    let allocation = prefix.allocate(64, 1).unwrap();
+    assert_eq!(allocation.prefix, "2a02:1802:5e:1::/64".as_bytes().to_vec());
+    assert_eq!(allocation.gateway, "2a02:1802:5e::1".as_bytes().to_vec());
+    assert_eq!(allocation.contract_id, 1);
+
+    // single addresses skip the first address and the gateway
+    assert_eq!(prefix.allocate(128, 2).unwrap().prefix, "2a02:1802:5e::2/128".as_bytes().to_vec());
+    assert_eq!(prefix.allocate(128, 3).unwrap().prefix, "2a02:1802:5e::3/128".as_bytes().to_vec());
+
+    assert_eq!(prefix.allocate(63, 4).unwrap().prefix, "2a02:1802:5e:2::/63".as_bytes().to_vec());
+    assert_eq!(prefix.allocate(64, 5), None);
+    assert_eq!(prefix.allocate(62, 5), None);
+    assert_eq!(prefix.allocations.len(), 4);
+}
+
+#[test]
+fn test_parse_ipv6_works() {
+    assert_eq!(super::types::parse_ipv6(b"::"), Some(0));
+    assert_eq!(super::types::parse_ipv6(b"::1"), Some(1));
+    assert_eq!(
+        super::types::parse_ipv6(b"2a02:1802:5e::1"),
+        Some(0x2a02_1802_005e_0000_0000_0000_0000_0001)
+    );
+    assert_eq!(super::types::parse_ipv6(b"1:2:3:4:5:6:7:8"), Some(0x0001_0002_0003_0004_0005_0006_0007_0008));
+
+    assert_eq!(super::types::parse_ipv6(b"1:2:3:4:5:6:7"), None);
+    assert_eq!(super::types::parse_ipv6(b"1:2:3:4::5:6:7:8"), None);
+    assert_eq!(super::types::parse_ipv6(b"1::2::3"), None);
+    assert_eq!(super::types::parse_ipv6(b"12345::"), None);
+    assert_eq!(super::types::parse_ipv6(b"g::"), None);
+    assert_eq!(super::types::parse_ipv6(b"185.206.122.1"), None);
+
+    assert_eq!(super::types::format_ipv6(0), "::".as_bytes().to_vec());
+    assert_eq!(super::types::format_ipv6(1), "::1".as_bytes().to_vec());
+    assert_eq!(
+        super::types::format_ipv6(0x2a02_1802_0000_0000_0001_0000_0000_0001),
+        "2a02:1802::1:0:0:1".as_bytes().to_vec()
+    );
+    assert_eq!(
+        super::types::format_ipv6(0x0001_0000_0002_0003_0004_0005_0006_0007),
+        "1:0:2:3:4:5:6:7".as_bytes().to_vec()
+    );
+}
+
+#[test]
+fn test_delete_farm_with_ipv6_prefix_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        assert_ok!(TfgridModule::remove_farm_ip(
+            Origin::signed(alice()),
+            1,
+            "185.206.122.33/24".as_bytes().to_vec()
+        ));
+        assert_ok!(TfgridModule::add_farm_ipv6_prefix(
+            Origin::signed(alice()),
+            1,
+            "2a02:1802:5e::/48".as_bytes().to_vec(),
+            "2a02:1802:5e::1".as_bytes().to_vec()
         ));
+
+        assert_noop!(
+            TfgridModule::delete_farm(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::CannotDeleteFarmWithPublicIPs
+        );
     });
 }
 
@@ -357,7 +797,7 @@ fn test_delete_farm_without_publicips_works() {
         assert_ok!(TfgridModule::remove_farm_ip(
             Origin::signed(alice()),
             1,
-            "1.1.1.0".as_bytes().to_vec()
+            "185.206.122.33/24".as_bytes().to_vec()
         ));
         assert_ok!(TfgridModule::delete_farm(Origin::signed(alice()), 1));
     });
@@ -374,7 +814,7 @@ fn test_delete_farm_with_nodes_fails() {
         assert_ok!(TfgridModule::remove_farm_ip(
             Origin::signed(alice()),
             1,
-            "1.1.1.0".as_bytes().to_vec()
+            "185.206.122.33/24".as_bytes().to_vec()
         ));
         assert_noop!(
             TfgridModule::delete_farm(Origin::signed(alice()), 1),
@@ -393,7 +833,7 @@ fn test_delete_farm_without_nodes_works() {
         assert_ok!(TfgridModule::remove_farm_ip(
             Origin::signed(alice()),
             1,
-            "1.1.1.0".as_bytes().to_vec()
+            "185.206.122.33/24".as_bytes().to_vec()
         ));
         assert_ok!(TfgridModule::delete_farm(Origin::signed(alice()), 1));
     });
@@ -409,227 +849,1247 @@ fn test_adding_ip_duplicate_to_farm_fails() {
         assert_ok!(TfgridModule::add_farm_ip(
             Origin::signed(alice()),
             1,
-            "1.1.1.2".as_bytes().to_vec(),
-            "1.1.1.1".as_bytes().to_vec()
+            "185.206.122.34/24".as_bytes().to_vec(),
+            "185.206.122.1".as_bytes().to_vec()
         ));
 
         assert_noop!(
             TfgridModule::add_farm_ip(
                 Origin::signed(alice()),
                 1,
-                "1.1.1.2".as_bytes().to_vec(),
-                "1.1.1.1".as_bytes().to_vec()
+                "185.206.122.34/24".as_bytes().to_vec(),
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::IpExists
+        );
+    });
+}
+
+#[test]
+fn test_adding_invalid_ip_to_farm_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let cases = vec![
+            ("185.206.122.34", "185.206.122.1", Error::<TestRuntime>::InvalidPublicIP),
+            ("185.206.122.34/33", "185.206.122.1", Error::<TestRuntime>::InvalidPublicIP),
+            ("185.206.256.34/24", "185.206.122.1", Error::<TestRuntime>::InvalidPublicIP),
+            ("some_ip", "185.206.122.1", Error::<TestRuntime>::InvalidPublicIP),
+            ("185.206.122.34/24", "185.206.122", Error::<TestRuntime>::InvalidGatewayIP),
+            ("185.206.122.34/24", "185.206.123.1", Error::<TestRuntime>::GatewayIPNotInSubnet),
+            ("185.206.122.34/24", "185.206.122.34", Error::<TestRuntime>::GatewayIPIsPublicIP),
+            // gateway of the ip that is already on the farm
+            ("185.206.122.1/24", "185.206.122.2", Error::<TestRuntime>::GatewayIPIsPublicIP),
+            // ip that is already on the farm
+            ("185.206.122.35/24", "185.206.122.33", Error::<TestRuntime>::GatewayIPIsPublicIP),
+            ("185.206.122.33/25", "185.206.122.1", Error::<TestRuntime>::IpExists),
+            ("10.0.0.2/24", "10.0.0.1", Error::<TestRuntime>::PublicIPIsReserved),
+            ("192.168.1.2/24", "192.168.1.1", Error::<TestRuntime>::PublicIPIsReserved),
+            ("127.0.0.2/8", "127.0.0.1", Error::<TestRuntime>::PublicIPIsReserved),
+            ("100.64.0.2/10", "100.64.0.1", Error::<TestRuntime>::PublicIPIsReserved),
+            ("185.206.122.34/0", "185.206.122.1", Error::<TestRuntime>::PublicIPPrefixTooShort),
+            ("185.206.122.34/15", "185.206.122.1", Error::<TestRuntime>::PublicIPPrefixTooShort),
+            ("185.206.122.0/24", "185.206.122.1", Error::<TestRuntime>::PublicIPIsNetworkOrBroadcastAddress),
+            ("185.206.122.255/24", "185.206.122.1", Error::<TestRuntime>::PublicIPIsNetworkOrBroadcastAddress),
+            ("185.206.122.34/31", "185.206.122.35", Error::<TestRuntime>::PublicIPIsNetworkOrBroadcastAddress),
+        ];
+
+        for (ip, gateway, error) in cases {
+            assert_noop!(
+                TfgridModule::add_farm_ip(
+                    Origin::signed(alice()),
+                    1,
+                    ip.as_bytes().to_vec(),
+                    gateway.as_bytes().to_vec()
+                ),
+                error
+            );
+        }
+    });
+}
+
+#[test]
+fn test_adding_ip_range_to_farm_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        assert_ok!(TfgridModule::add_farm_ip_range(
+            Origin::signed(alice()),
+            1,
+            "185.206.122.40/24".as_bytes().to_vec(),
+            8,
+            "185.206.122.1".as_bytes().to_vec()
+        ));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips.len(), 9);
+        assert_eq!(farm.public_ips[1].ip, "185.206.122.40/24".as_bytes().to_vec());
+        assert_eq!(farm.public_ips[8].ip, "185.206.122.47/24".as_bytes().to_vec());
+        assert_eq!(farm.public_ips[8].gateway, "185.206.122.1".as_bytes().to_vec());
+
+        assert_ok!(TfgridModule::remove_farm_ip_range(
+            Origin::signed(alice()),
+            1,
+            "185.206.122.42/24".as_bytes().to_vec(),
+            4
+        ));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.public_ips.len(), 5);
+        assert_eq!(farm.public_ips[3].ip, "185.206.122.46/24".as_bytes().to_vec());
+    });
+}
+
+#[test]
+fn test_ip_range_weight_scales_with_range_and_farm_ips() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let call = |count| {
+            super::Call::<TestRuntime>::add_farm_ip_range(
+                1,
+                "185.206.122.100/24".as_bytes().to_vec(),
+                count,
+                "185.206.122.1".as_bytes().to_vec(),
+            )
+        };
+        let single_ip_weight = call(1).get_dispatch_info().weight;
+        let range_weight = call(64).get_dispatch_info().weight;
+        assert!(range_weight > single_ip_weight);
+
+        // the weight of a full farm is charged, the unused part is refunded
+        let add_range = |start_ip: &str| {
+            TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                start_ip.as_bytes().to_vec(),
+                16,
+                "185.206.122.1".as_bytes().to_vec(),
+            )
+            .unwrap()
+            .actual_weight
+            .unwrap()
+        };
+        let declared_weight = call(16).get_dispatch_info().weight;
+        let first_weight = add_range("185.206.122.2/24");
+        assert!(first_weight < declared_weight);
+        assert!(add_range("185.206.122.50/24") > first_weight);
+    });
+}
+
+#[test]
+fn test_farm_public_ips_are_capped() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let gateway = "185.206.0.1".as_bytes().to_vec();
+        for start_ip in &["185.206.1.0/16", "185.206.2.0/16", "185.206.3.0/16"] {
+            assert_ok!(TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                start_ip.as_bytes().to_vec(),
+                256,
+                gateway.clone()
+            ));
+        }
+        let remaining = super::MAX_FARM_PUBLIC_IPS - TfgridModule::farms(1).public_ips.len() as u32;
+        assert_ok!(TfgridModule::add_farm_ip_range(
+            Origin::signed(alice()),
+            1,
+            "185.206.4.0/16".as_bytes().to_vec(),
+            remaining,
+            gateway.clone()
+        ));
+        assert_eq!(
+            TfgridModule::farms(1).public_ips.len() as u32,
+            super::MAX_FARM_PUBLIC_IPS
+        );
+
+        assert_noop!(
+            TfgridModule::add_farm_ip(
+                Origin::signed(alice()),
+                1,
+                "185.206.5.0/16".as_bytes().to_vec(),
+                gateway
+            ),
+            Error::<TestRuntime>::TooManyFarmPublicIPs
+        );
+    });
+}
+
+#[test]
+fn test_adding_ip_range_to_farm_is_all_or_nothing() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        // overlaps with 185.206.122.33 which is already on the farm
+        assert_noop!(
+            TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.30/24".as_bytes().to_vec(),
+                8,
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::IpExists
+        );
+
+        // runs into the broadcast address of the subnet
+        assert_noop!(
+            TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.250/24".as_bytes().to_vec(),
+                10,
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::PublicIPIsNetworkOrBroadcastAddress
+        );
+
+        assert_noop!(
+            TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.40/24".as_bytes().to_vec(),
+                0,
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::InvalidIPRange
+        );
+
+        assert_noop!(
+            TfgridModule::add_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.0.0/16".as_bytes().to_vec(),
+                super::MAX_IP_RANGE_SIZE + 1,
+                "185.206.122.1".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::InvalidIPRange
+        );
+
+        // 185.206.122.34 is not on the farm
+        assert_noop!(
+            TfgridModule::remove_farm_ip_range(
+                Origin::signed(alice()),
+                1,
+                "185.206.122.33/24".as_bytes().to_vec(),
+                2
+            ),
+            Error::<TestRuntime>::IpNotExists
+        );
+    });
+}
+
+#[test]
+fn test_create_farm_with_gateway_listed_as_ip_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+
+        let farm_name = "test_farm".as_bytes().to_vec();
+        let pub_ips = vec![
+            super::types::PublicIP {
+                ip: "185.206.122.33/24".as_bytes().to_vec(),
+                gateway: "185.206.122.1".as_bytes().to_vec(),
+                contract_id: 0,
+            },
+            super::types::PublicIP {
+                ip: "185.206.122.1/24".as_bytes().to_vec(),
+                gateway: "185.206.122.2".as_bytes().to_vec(),
+                contract_id: 0,
+            },
+        ];
+
+        let document = "some_link".as_bytes().to_vec();
+        let hash = "some_hash".as_bytes().to_vec();
+
+        assert_ok!(TfgridModule::farmer_accept_tc(
+            Origin::signed(alice()),
+            document,
+            hash,
+        ));
+
+        assert_noop!(
+            TfgridModule::create_farm(Origin::signed(alice()), farm_name, pub_ips),
+            Error::<TestRuntime>::GatewayIPIsPublicIP
+        );
+    });
+}
+
+#[test]
+fn test_update_twin_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_twin();
+
+        let ip = "some_other_ip".as_bytes().to_vec();
+        assert_ok!(TfgridModule::update_twin(Origin::signed(alice()), ip));
+    });
+}
+
+#[test]
+fn test_update_twin_fails_if_signed_by_someone_else() {
+    ExternalityBuilder::build().execute_with(|| {
+        let document = "some_link".as_bytes().to_vec();
+        let hash = "some_hash".as_bytes().to_vec();
+    
+        assert_ok!(TfgridModule::user_accept_tc(
+            Origin::signed(alice()),
+            document,
+            hash,
+        ));
+
+        let mut ip = "some_ip".as_bytes().to_vec();
+        assert_ok!(TfgridModule::create_twin(Origin::signed(alice()), ip));
+
+        ip = "some_other_ip".as_bytes().to_vec();
+        assert_noop!(
+            TfgridModule::update_twin(Origin::signed(bob()), ip),
+            Error::<TestRuntime>::TwinNotExists
+        );
+    });
+}
+
+#[test]
+fn test_create_farm_with_same_name_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let farm_name = "test_farm".as_bytes().to_vec();
+        let mut pub_ips = Vec::new();
+        pub_ips.push(super::types::PublicIP {
+            ip: "185.206.122.33/24".as_bytes().to_vec(),
+            gateway: "185.206.122.1".as_bytes().to_vec(),
+            contract_id: 0,
+        });
+
+        assert_noop!(
+            TfgridModule::create_farm(Origin::signed(alice()), farm_name, pub_ips),
+            Error::<TestRuntime>::FarmExists
+        );
+    });
+}
+
+#[test]
+fn test_farm_add_stellar_payout_address() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+
+        let addr = "some_address".as_bytes().to_vec();
+        assert_ok!(TfgridModule::add_stellar_payout_v2address(
+            Origin::signed(alice()),
+            1,
+            addr
+        ));
+
+        let addr2 = "some_other_address".as_bytes().to_vec();
+        assert_ok!(TfgridModule::add_stellar_payout_v2address(
+            Origin::signed(alice()),
+            1,
+            addr2
+        ));
+    });
+}
+
+#[test]
+fn test_farm_transfer_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_twin_bob();
+        create_farm();
+
+        let addr = "some_address".as_bytes().to_vec();
+        assert_ok!(TfgridModule::add_stellar_payout_v2address(
+            Origin::signed(alice()),
+            1,
+            addr.clone()
+        ));
+
+        assert_ok!(TfgridModule::offer_farm_transfer(Origin::signed(alice()), 1, 2));
+        assert_eq!(TfgridModule::farm_transfer_offers(1), 2);
+
+        let document = "some_link".as_bytes().to_vec();
+        let hash = "some_hash".as_bytes().to_vec();
+        assert_ok!(TfgridModule::farmer_accept_tc(Origin::signed(bob()), document, hash));
+        assert_ok!(TfgridModule::accept_farm_transfer(Origin::signed(bob()), 1));
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.twin_id, 2);
+        assert_eq!(TfgridModule::farm_payout_address_by_farm_id(1), addr);
+        assert_eq!(TfgridModule::farm_transfer_offers(1), 0);
+
+        // previous owner cannot manage the farm anymore
+        assert_noop!(
+            TfgridModule::update_farm(Origin::signed(alice()), 1, "new_name".as_bytes().to_vec(), 1),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+        assert_ok!(TfgridModule::update_farm(
+            Origin::signed(bob()),
+            1,
+            "new_name".as_bytes().to_vec(),
+            1
+        ));
+    });
+}
+
+#[test]
+fn test_farm_transfer_without_farmer_terms_and_conditions_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_twin_bob();
+        create_farm();
+
+        assert_ok!(TfgridModule::offer_farm_transfer(Origin::signed(alice()), 1, 2));
+        assert_noop!(
+            TfgridModule::accept_farm_transfer(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmerDidNotSignTermsAndConditions
+        );
+    });
+}
+
+#[test]
+fn test_farm_transfer_not_offered_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_twin_bob();
+        create_farm();
+
+        let document = "some_link".as_bytes().to_vec();
+        let hash = "some_hash".as_bytes().to_vec();
+        assert_ok!(TfgridModule::farmer_accept_tc(Origin::signed(bob()), document, hash));
+
+        assert_noop!(
+            TfgridModule::accept_farm_transfer(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmTransferNotOffered
+        );
+
+        // only the farm owner can offer the farm
+        assert_noop!(
+            TfgridModule::offer_farm_transfer(Origin::signed(bob()), 1, 2),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+        assert_noop!(
+            TfgridModule::offer_farm_transfer(Origin::signed(alice()), 1, 1),
+            Error::<TestRuntime>::FarmTransferToSameTwin
+        );
+
+        assert_ok!(TfgridModule::offer_farm_transfer(Origin::signed(alice()), 1, 2));
+        assert_ok!(TfgridModule::cancel_farm_transfer(Origin::signed(alice()), 1));
+        assert_noop!(
+            TfgridModule::accept_farm_transfer(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmTransferNotOffered
+        );
+    });
+}
+
+#[test]
+fn create_node_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+    });
+}
+
+#[test]
+fn set_certification_type_node_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        assert_ok!(TfgridModule::set_node_certification(RawOrigin::Root.into(), 1, super::types::CertificationType::Certified));
+        let node = TfgridModule::nodes(1);
+        assert_eq!(node.certification_type, super::types::CertificationType::Certified);
+
+        assert_ok!(TfgridModule::set_node_certification(RawOrigin::Root.into(), 1, super::types::CertificationType::Diy));
+        let node = TfgridModule::nodes(1);
+        assert_eq!(node.certification_type, super::types::CertificationType::Diy);
+    });
+}
+
+#[test]
+fn node_report_uptime_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        Timestamp::set_timestamp(1628082000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 500));
+    });
+}
+
+#[test]
+fn node_uptime_is_accounted_over_periods() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        // start of uptime period 452245
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        // first report only anchors the tracker
+        Timestamp::set_timestamp((1628082000 + 100) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 100));
+        assert_eq!(TfgridModule::node_uptime_history_by_id(1).len(), 0);
+
+        // node did not report for an hour, the uptime spans two periods
+        Timestamp::set_timestamp((1628082000 + 3700) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 3700));
+
+        let history = TfgridModule::node_uptime_history_by_id(1);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].period, 452245);
+        assert_eq!(history[0].uptime, 3500);
+        assert_eq!(history[1].period, 452246);
+        assert_eq!(history[1].uptime, 100);
+
+        assert_eq!(
+            TfgridModule::get_node_availability(1, 452245),
+            Perbill::from_rational_approximation(3500u64, 3600u64)
+        );
+        // only the part of the current period that already passed is taken into account
+        assert_eq!(TfgridModule::get_node_availability(1, 452246), Perbill::one());
+        assert_eq!(TfgridModule::get_node_availability(1, 452247), Perbill::zero());
+    });
+}
+
+#[test]
+fn node_uptime_detects_reboot() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        Timestamp::set_timestamp((1628082000 + 100) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 1000));
+
+        // uptime went backwards, node rebooted 300 seconds ago
+        Timestamp::set_timestamp((1628082000 + 1000) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 300));
+
+        let history = TfgridModule::node_uptime_history_by_id(1);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].uptime, 300);
+        assert_eq!(history[0].reboots, 1);
+
+        let tracker = TfgridModule::node_uptime_tracker_by_id(1);
+        assert_eq!(tracker.last_uptime, 300);
+        assert_eq!(tracker.last_report, 1628082000 + 1000);
+    });
+}
+
+#[test]
+fn node_uptime_is_capped_on_clock_skew() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        Timestamp::set_timestamp((1628082000 + 100) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 100));
+
+        // node reports more uptime than the time that passed on chain
+        Timestamp::set_timestamp((1628082000 + 200) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 5000));
+
+        let history = TfgridModule::node_uptime_history_by_id(1);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].uptime, 100);
+        assert_eq!(history[0].reboots, 0);
+    });
+}
+
+#[test]
+fn node_uptime_history_is_bounded() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        for i in 0..40 {
+            Timestamp::set_timestamp((1628082000 + i * 3600) * 1000);
+            assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), i * 3600));
+        }
+
+        let history = TfgridModule::node_uptime_history_by_id(1);
+        assert_eq!(history.len(), super::MAX_UPTIME_PERIODS);
+        assert_eq!(history[0].period, 452245 + 39 - super::MAX_UPTIME_PERIODS as u64);
+    });
+}
+
+#[test]
+fn node_uptime_after_long_gap_only_accounts_kept_periods() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        Timestamp::set_timestamp((1628082000 + 100) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 100));
+
+        // a single report spanning far more periods than the history keeps
+        let gap = 1000 * 3600;
+        Timestamp::set_timestamp((1628082000 + 100 + gap) * 1000);
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 100 + gap));
+
+        let now = 1628082000 + 100 + gap;
+        let history = TfgridModule::node_uptime_history_by_id(1);
+        assert_eq!(history.len(), super::MAX_UPTIME_PERIODS);
+        assert_eq!(history[history.len() - 1].period, now / 3600);
+        let uptime: u64 = history.iter().map(|bucket| bucket.uptime).sum();
+        assert!(uptime <= super::MAX_UPTIME_PERIODS as u64 * 3600);
+    });
+}
+
+#[test]
+fn node_status_goes_down_after_heartbeat_window() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        Timestamp::set_timestamp(1628082000 * 1000);
+        create_node();
+
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Up);
+
+        // no uptime report within the heartbeat window
+        Timestamp::set_timestamp((1628082000 + 7201) * 1000);
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Down);
+
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 500));
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Up);
+
+        let availability = TfgridModule::node_availability_by_id(1);
+        assert_eq!(availability.last_seen, 1628082000 + 7201);
+    });
+}
+
+#[test]
+fn node_standby_is_woken_up_by_uptime_report() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        assert_ok!(TfgridModule::set_node_standby(Origin::signed(alice()), 1));
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Standby);
+
+        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 10));
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Up);
+    });
+}
+
+#[test]
+fn node_set_standby_fails_if_not_farmer() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_twin_bob();
+        create_farm();
+        create_node();
+
+        assert_noop!(
+            TfgridModule::set_node_standby(Origin::signed(bob()), 1),
+            Error::<TestRuntime>::FarmerNotAuthorized
+        );
+    });
+}
+
+#[test]
+fn decommissioned_node_cannot_report_uptime() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        assert_ok!(TfgridModule::decommission_node(Origin::signed(alice()), 1));
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Decommissioned);
+
+        assert_noop!(
+            TfgridModule::report_uptime(Origin::signed(alice()), 500),
+            Error::<TestRuntime>::NodeIsDecommissioned
+        );
+        assert_noop!(
+            TfgridModule::set_node_standby(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::NodeIsDecommissioned
+        );
+    });
+}
+
+#[test]
+fn node_add_public_config_works() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        let pub_config = super::types::PublicConfig {
+            ipv4: "some_ip".as_bytes().to_vec(),
+            ipv6: "some_ip".as_bytes().to_vec(),
+            gw4: "some_ip".as_bytes().to_vec(),
+            gw6: "some_ip".as_bytes().to_vec(),
+            domain: "some_domain".as_bytes().to_vec(),
+        };
+
+        assert_ok!(TfgridModule::add_node_public_config(
+            Origin::signed(alice()),
+            1,
+            1,
+            pub_config.clone()
+        ));
+
+        let node = TfgridModule::nodes(1);
+        assert_eq!(node.public_config, Some(pub_config));
+    });
+}
+
+#[test]
+fn node_add_public_config_fails_if_signature_incorrect() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        let pub_config = super::types::PublicConfig {
+            ipv4: "some_ip".as_bytes().to_vec(),
+            ipv6: "some_ip".as_bytes().to_vec(),
+            gw4: "some_ip".as_bytes().to_vec(),
+            gw6: "some_ip".as_bytes().to_vec(),
+            domain: "some_domain".as_bytes().to_vec(),
+        };
+
+        assert_noop!(
+            TfgridModule::add_node_public_config(Origin::signed(bob()), 1, 1, pub_config.clone()),
+            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+        );
+    });
+}
+
+#[test]
+fn create_node_with_same_pubkey_fails() {
+    ExternalityBuilder::build().execute_with(|| {
+        create_entity();
+        create_twin();
+        create_farm();
+        create_node();
+
+        // random location
+        let location = super::types::Location {
+            longitude: "12.233213231".as_bytes().to_vec(),
+            latitude: "32.323112123".as_bytes().to_vec(),
+        };
+
+        let resources = super::types::Resources {
+            hru: 1,
+            sru: 1,
+            cru: 1,
+            mru: 1,
+        };
+
+        let country_id = 1;
+        let city_id = 1;
+
+        assert_noop!(
+            TfgridModule::create_node(
+                Origin::signed(alice()),
+                1,
+                resources,
+                location,
+                country_id,
+                city_id,
+                Vec::new()
             ),
-            Error::<TestRuntime>::IpExists
+            Error::<TestRuntime>::NodeWithTwinIdExists
         );
     });
 }
 
 #[test]
-fn test_update_twin_works() {
+fn create_gateway_works() {
     ExternalityBuilder::build().execute_with(|| {
+        create_entity();
         create_twin();
+        create_farm();
+        create_gateway();
 
-        let ip = "some_other_ip".as_bytes().to_vec();
-        assert_ok!(TfgridModule::update_twin(Origin::signed(alice()), ip));
+        let gateway = TfgridModule::gateways(1);
+        assert_eq!(gateway.farm_id, 1);
+        assert_eq!(gateway.account_id, test_ed25519());
+        assert_eq!(TfgridModule::gateway_ids_by_farm_id(1), vec![1]);
     });
 }
 
 #[test]
-fn test_update_twin_fails_if_signed_by_someone_else() {
+fn create_gateway_fails_if_not_farmer() {
     ExternalityBuilder::build().execute_with(|| {
-        let document = "some_link".as_bytes().to_vec();
-        let hash = "some_hash".as_bytes().to_vec();
-    
-        assert_ok!(TfgridModule::user_accept_tc(
-            Origin::signed(alice()),
-            document,
-            hash,
-        ));
-
-        let mut ip = "some_ip".as_bytes().to_vec();
-        assert_ok!(TfgridModule::create_twin(Origin::signed(alice()), ip));
+        create_entity();
+        create_twin();
+        create_twin_bob();
+        create_farm();
 
-        ip = "some_other_ip".as_bytes().to_vec();
+        let location = super::types::Location {
+            longitude: "12.233213231".as_bytes().to_vec(),
+            latitude: "32.323112123".as_bytes().to_vec(),
+        };
         assert_noop!(
-            TfgridModule::update_twin(Origin::signed(bob()), ip),
-            Error::<TestRuntime>::TwinNotExists
+            TfgridModule::create_gateway(
+                Origin::signed(bob()),
+                1,
+                location,
+                1,
+                1,
+                "some_pub_key".as_bytes().to_vec(),
+                test_ed25519()
+            ),
+            Error::<TestRuntime>::FarmerNotAuthorized
         );
     });
 }
 
 #[test]
-fn test_create_farm_with_same_name_fails() {
+fn update_gateway_works() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
         create_twin();
         create_farm();
+        create_gateway();
 
-        let farm_name = "test_farm".as_bytes().to_vec();
-        let mut pub_ips = Vec::new();
-        pub_ips.push(super::types::PublicIP {
-            ip: "1.1.1.0".as_bytes().to_vec(),
-            gateway: "1.1.1.1".as_bytes().to_vec(),
-            contract_id: 0,
-        });
+        assert_ok!(TfgridModule::create_country(
+            RawOrigin::Root.into(),
+            "Netherlands".as_bytes().to_vec(),
+            "NL".as_bytes().to_vec()
+        ));
+        assert_ok!(TfgridModule::create_city(
+            RawOrigin::Root.into(),
+            2,
+            "Amsterdam".as_bytes().to_vec()
+        ));
+
+        let location = super::types::Location {
+            longitude: "4.3499".as_bytes().to_vec(),
+            latitude: "50.8505".as_bytes().to_vec(),
+        };
+        assert_ok!(TfgridModule::update_gateway(
+            Origin::signed(alice()),
+            1,
+            location.clone(),
+            2,
+            2,
+            "other_pub_key".as_bytes().to_vec(),
+            test_sr25519()
+        ));
+
+        let gateway = TfgridModule::gateways(1);
+        assert_eq!(
+            gateway.location,
+            super::types::Coordinates {
+                latitude: 50850500,
+                longitude: 4349900
+            }
+        );
+        assert_eq!(gateway.country_id, 2);
+        assert_eq!(gateway.account_id, test_sr25519());
 
         assert_noop!(
-            TfgridModule::create_farm(Origin::signed(alice()), farm_name, pub_ips),
-            Error::<TestRuntime>::FarmExists
+            TfgridModule::update_gateway(
+                Origin::signed(alice()),
+                2,
+                location,
+                2,
+                2,
+                "other_pub_key".as_bytes().to_vec(),
+                test_sr25519()
+            ),
+            Error::<TestRuntime>::GatewayNotExists
         );
     });
 }
 
 #[test]
-fn test_farm_add_stellar_payout_address() {
+fn delete_farm_with_gateways_fails() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
         create_twin();
         create_farm();
+        create_gateway();
 
-        let addr = "some_address".as_bytes().to_vec();
-        assert_ok!(TfgridModule::add_stellar_payout_v2address(
+        assert_ok!(TfgridModule::remove_farm_ip(
             Origin::signed(alice()),
             1,
-            addr
+            "185.206.122.33/24".as_bytes().to_vec()
         ));
+        assert_noop!(
+            TfgridModule::delete_farm(Origin::signed(alice()), 1),
+            Error::<TestRuntime>::CannotDeleteFarmWithGatewaysAssigned
+        );
 
-        let addr2 = "some_other_address".as_bytes().to_vec();
-        assert_ok!(TfgridModule::add_stellar_payout_v2address(
-            Origin::signed(alice()),
-            1,
-            addr2
-        ));
+        assert_ok!(TfgridModule::delete_gateway(Origin::signed(alice()), 1));
+        assert_eq!(TfgridModule::gateway_ids_by_farm_id(1).len(), 0);
+        assert_ok!(TfgridModule::delete_farm(Origin::signed(alice()), 1));
     });
 }
 
 #[test]
-fn create_node_works() {
+fn create_country_and_city_works() {
     ExternalityBuilder::build().execute_with(|| {
-        create_entity();
-        create_twin();
-        create_farm();
-        create_node();
+        assert_ok!(TfgridModule::create_country(
+            RawOrigin::Root.into(),
+            "Netherlands".as_bytes().to_vec(),
+            "NL".as_bytes().to_vec()
+        ));
+        assert_eq!(TfgridModule::country_id_by_code("NL".as_bytes().to_vec()), 2);
+
+        assert_ok!(TfgridModule::create_city(
+            RawOrigin::Root.into(),
+            2,
+            "Amsterdam".as_bytes().to_vec()
+        ));
+        let city = TfgridModule::cities(2);
+        assert_eq!(city.country_id, 2);
+
+        assert_noop!(
+            TfgridModule::create_country(
+                RawOrigin::Root.into(),
+                "Nederland".as_bytes().to_vec(),
+                "NL".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::CountryExists
+        );
+        assert_noop!(
+            TfgridModule::create_country(
+                RawOrigin::Root.into(),
+                "Netherlands".as_bytes().to_vec(),
+                "nld".as_bytes().to_vec()
+            ),
+            Error::<TestRuntime>::InvalidCountryCode
+        );
+        assert_noop!(
+            TfgridModule::create_city(RawOrigin::Root.into(), 2, "Amsterdam".as_bytes().to_vec()),
+            Error::<TestRuntime>::CityExists
+        );
+        assert_noop!(
+            TfgridModule::create_city(RawOrigin::Root.into(), 3, "Paris".as_bytes().to_vec()),
+            Error::<TestRuntime>::CountryNotExists
+        );
+
+        // only the restricted origin can manage the registry
+        assert_noop!(
+            TfgridModule::create_country(
+                Origin::signed(alice()),
+                "France".as_bytes().to_vec(),
+                "FR".as_bytes().to_vec()
+            ),
+            sp_runtime::DispatchError::BadOrigin
+        );
+
+        assert_ok!(TfgridModule::update_city(
+            RawOrigin::Root.into(),
+            2,
+            "Rotterdam".as_bytes().to_vec()
+        ));
+        assert_eq!(TfgridModule::city_id_by_country_and_name(2, "Rotterdam".as_bytes().to_vec()), 2);
+        assert_eq!(TfgridModule::city_id_by_country_and_name(2, "Amsterdam".as_bytes().to_vec()), 0);
     });
 }
 
 #[test]
-fn set_certification_type_node_works() {
+fn create_node_with_unknown_city_fails() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
         create_twin();
         create_farm();
-        create_node();
 
-        assert_ok!(TfgridModule::set_node_certification(RawOrigin::Root.into(), 1, super::types::CertificationType::Certified));
-        let node = TfgridModule::nodes(1);
-        assert_eq!(node.certification_type, super::types::CertificationType::Certified);
+        assert_ok!(TfgridModule::create_country(
+            RawOrigin::Root.into(),
+            "Netherlands".as_bytes().to_vec(),
+            "NL".as_bytes().to_vec()
+        ));
 
-        assert_ok!(TfgridModule::set_node_certification(RawOrigin::Root.into(), 1, super::types::CertificationType::Diy));
-        let node = TfgridModule::nodes(1);
-        assert_eq!(node.certification_type, super::types::CertificationType::Diy);
+        let location = super::types::Location {
+            longitude: "12.233213231".as_bytes().to_vec(),
+            latitude: "32.323112123".as_bytes().to_vec(),
+        };
+        let resources = super::types::Resources {
+            hru: 1,
+            sru: 1,
+            cru: 1,
+            mru: 1,
+        };
+
+        assert_noop!(
+            TfgridModule::create_node(
+                Origin::signed(alice()),
+                1,
+                resources,
+                location.clone(),
+                1,
+                2,
+                Vec::new()
+            ),
+            Error::<TestRuntime>::CityNotExists
+        );
+        // Ghent is not in the Netherlands
+        assert_noop!(
+            TfgridModule::create_node(
+                Origin::signed(alice()),
+                1,
+                resources,
+                location,
+                2,
+                1,
+                Vec::new()
+            ),
+            Error::<TestRuntime>::CityNotInCountry
+        );
     });
 }
 
 #[test]
-fn node_report_uptime_works() {
+fn migrate_original_layout_maps_country_and_city_names_to_ids() {
     ExternalityBuilder::build().execute_with(|| {
-        create_entity();
-        create_twin();
-        create_farm();
-        create_node();
+        // V3Struct is the last version of the original layout
+        put_storage_value(b"TfgridModule", b"PalletVersion", &[], super::types::StorageVersion::V3Struct);
+
+        let old_node = super::types::deprecated::NodeV3 {
+            version: 3,
+            id: 1,
+            farm_id: 1,
+            twin_id: 1,
+            location: super::types::Location {
+                longitude: "4.3517".as_bytes().to_vec(),
+                latitude: "50.8503".as_bytes().to_vec(),
+            },
+            country: "Belgium".as_bytes().to_vec(),
+            city: "Brussels".as_bytes().to_vec(),
+            ..Default::default()
+        };
+        put_storage_value(b"TfgridModule", b"Nodes", &1u32.using_encoded(Blake2_128Concat::hash), old_node);
+
+        let old_entity = super::types::deprecated::EntityV1 {
+            version: 1,
+            id: 1,
+            name: "foobar".as_bytes().to_vec(),
+            account_id: alice(),
+            country: "France".as_bytes().to_vec(),
+            city: "Paris".as_bytes().to_vec(),
+        };
+        put_storage_value(b"TfgridModule", b"Entities", &1u32.using_encoded(Blake2_128Concat::hash), old_entity);
+
+        let old_node_without_location = super::types::deprecated::NodeV3 {
+            version: 3,
+            id: 2,
+            location: super::types::Location {
+                longitude: "somewhere".as_bytes().to_vec(),
+                latitude: "over the rainbow".as_bytes().to_vec(),
+            },
+            ..Default::default()
+        };
+        put_storage_value(b"TfgridModule", b"Nodes", &2u32.using_encoded(Blake2_128Concat::hash), old_node_without_location);
+
+        let old_farm = super::types::deprecated::FarmV1 {
+            version: 1,
+            id: 1,
+            name: "test_farm".as_bytes().to_vec(),
+            twin_id: 1,
+            pricing_policy_id: 1,
+            certification_type: super::types::CertificationType::Diy,
+            public_ips: vec![super::types::PublicIP {
+                ip: "185.206.122.33/24".as_bytes().to_vec(),
+                gateway: "185.206.122.1".as_bytes().to_vec(),
+                contract_id: 1,
+            }],
+        };
+        put_storage_value(b"TfgridModule", b"Farms", &1u32.using_encoded(Blake2_128Concat::hash), old_farm);
 
-        Timestamp::set_timestamp(1628082000);
-        assert_ok!(TfgridModule::report_uptime(Origin::signed(alice()), 500));
+        TfgridModule::on_runtime_upgrade();
+
+        // Belgium already exists in the registry, Brussels is created
+        let node = TfgridModule::nodes(1);
+        assert_eq!(node.version, super::TFGRID_NODE_VERSION);
+        assert_eq!(node.country_id, 1);
+        assert_eq!(node.city_id, 2);
+        assert_eq!(TfgridModule::cities(2).name, "Brussels".as_bytes().to_vec());
+        assert_eq!(node.location.latitude, 50850300);
+        assert_eq!(node.location.longitude, 4351700);
+        // existing nodes keep accepting deployments
+        assert_eq!(TfgridModule::node_availability_by_id(1).status, super::types::NodeStatus::Up);
+        assert_eq!(TfgridModule::get_node_status(1), super::types::NodeStatus::Up);
+
+        let entity = TfgridModule::entities(1);
+        assert_eq!(entity.country_id, 2);
+        assert_eq!(entity.city_id, 3);
+        assert_eq!(TfgridModule::countries(2).name, "France".as_bytes().to_vec());
+        assert_eq!(TfgridModule::countries(2).code.len(), 0);
+
+        // invalid locations and empty countries are reset
+        let node = TfgridModule::nodes(2);
+        assert_eq!(node.location, super::types::Coordinates::default());
+        assert_eq!(node.country_id, 0);
+        assert_eq!(node.city_id, 0);
+
+        let farm = TfgridModule::farms(1);
+        assert_eq!(farm.version, super::TFGRID_FARM_VERSION);
+        assert_eq!(farm.name, "test_farm".as_bytes().to_vec());
+        assert_eq!(farm.public_ips[0].contract_id, 1);
+        assert_eq!(farm.public_ipv6_prefixes.len(), 0);
+
+        assert_eq!(TfgridModule::pallet_version(), super::types::StorageVersion::V4Struct);
     });
 }
 
 #[test]
-fn node_add_public_config_works() {
-    ExternalityBuilder::build().execute_with(|| {
-        create_entity();
-        create_twin();
-        create_farm();
-        create_node();
+fn migrate_on_new_chain_does_nothing() {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<TestRuntime>()
+        .unwrap();
+    super::GenesisConfig::<TestRuntime>::default()
+        .assimilate_storage(&mut storage)
+        .unwrap();
 
-        let pub_config = super::types::PublicConfig {
-            ipv4: "some_ip".as_bytes().to_vec(),
-            ipv6: "some_ip".as_bytes().to_vec(),
-            gw4: "some_ip".as_bytes().to_vec(),
-            gw6: "some_ip".as_bytes().to_vec(),
-            domain: "some_domain".as_bytes().to_vec(),
-        };
+    sp_io::TestExternalities::from(storage).execute_with(|| {
+        assert_eq!(TfgridModule::pallet_version(), super::types::StorageVersion::V4Struct);
+        let pricing_policy = TfgridModule::pricing_policies(1);
 
-        assert_ok!(TfgridModule::add_node_public_config(
-            Origin::signed(alice()),
-            1,
-            1,
-            pub_config.clone()
-        ));
+        assert_eq!(TfgridModule::migrate_storage(), 0);
 
-        let node = TfgridModule::nodes(1);
-        assert_eq!(node.public_config, Some(pub_config));
+        assert_eq!(TfgridModule::pricing_policies(1), pricing_policy);
+        assert_eq!(TfgridModule::pallet_version(), super::types::StorageVersion::V4Struct);
     });
 }
 
 #[test]
-fn node_add_public_config_fails_if_signature_incorrect() {
+fn parse_location_works() {
+    let location = |latitude: &str, longitude: &str| super::types::Location {
+        longitude: longitude.as_bytes().to_vec(),
+        latitude: latitude.as_bytes().to_vec(),
+    };
+
+    assert_eq!(location("32.323112123", "0").parse_latitude(), Some(32323112));
+    assert_eq!(location("-90", "0").parse_latitude(), Some(-90000000));
+    assert_eq!(location("+1.5", "0").parse_latitude(), Some(1500000));
+    assert_eq!(location("0", "-180.000000").parse_longitude(), Some(-180000000));
+
+    assert_eq!(location("90.000001", "0").parse_latitude(), None);
+    assert_eq!(location("0", "180.5").parse_longitude(), None);
+    assert_eq!(location("", "0").parse_latitude(), None);
+    assert_eq!(location("1.", "0").parse_latitude(), None);
+    assert_eq!(location(".5", "0").parse_latitude(), None);
+    assert_eq!(location("1.2.3", "0").parse_latitude(), None);
+    assert_eq!(location("12a", "0").parse_latitude(), None);
+    assert_eq!(location("1000", "0").parse_latitude(), None);
+}
+
+#[test]
+fn create_node_with_invalid_location_fails() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
         create_twin();
         create_farm();
-        create_node();
 
-        let pub_config = super::types::PublicConfig {
-            ipv4: "some_ip".as_bytes().to_vec(),
-            ipv6: "some_ip".as_bytes().to_vec(),
-            gw4: "some_ip".as_bytes().to_vec(),
-            gw6: "some_ip".as_bytes().to_vec(),
-            domain: "some_domain".as_bytes().to_vec(),
+        let resources = super::types::Resources {
+            hru: 1,
+            sru: 1,
+            cru: 1,
+            mru: 1,
         };
 
+        let location = super::types::Location {
+            longitude: "12.233213231".as_bytes().to_vec(),
+            latitude: "95.1".as_bytes().to_vec(),
+        };
         assert_noop!(
-            TfgridModule::add_node_public_config(Origin::signed(bob()), 1, 1, pub_config.clone()),
-            Error::<TestRuntime>::CannotUpdateFarmWrongTwin
+            TfgridModule::create_node(Origin::signed(alice()), 1, resources, location, 1, 1, Vec::new()),
+            Error::<TestRuntime>::InvalidLatitude
+        );
+
+        let location = super::types::Location {
+            longitude: "east".as_bytes().to_vec(),
+            latitude: "32.323112123".as_bytes().to_vec(),
+        };
+        assert_noop!(
+            TfgridModule::create_node(Origin::signed(alice()), 1, resources, location, 1, 1, Vec::new()),
+            Error::<TestRuntime>::InvalidLongitude
         );
     });
 }
 
 #[test]
-fn create_node_with_same_pubkey_fails() {
+fn nodes_in_bounding_box_works() {
     ExternalityBuilder::build().execute_with(|| {
         create_entity();
         create_twin();
+        create_twin_bob();
         create_farm();
+        // located at 32.323112123, 12.233213231
         create_node();
 
-        // random location
-        let location = super::types::Location {
-            longitude: "12.233213231".as_bytes().to_vec(),
-            latitude: "32.323112123".as_bytes().to_vec(),
-        };
-
         let resources = super::types::Resources {
             hru: 1,
             sru: 1,
             cru: 1,
             mru: 1,
         };
+        let location = super::types::Location {
+            longitude: "179.5".as_bytes().to_vec(),
+            latitude: "-17.7".as_bytes().to_vec(),
+        };
+        assert_ok!(TfgridModule::create_node(
+            Origin::signed(bob()),
+            1,
+            resources,
+            location,
+            1,
+            1,
+            Vec::new()
+        ));
 
-        let country = "Belgium".as_bytes().to_vec();
-        let city = "Ghent".as_bytes().to_vec();
-
-        assert_noop!(
-            TfgridModule::create_node(
-                Origin::signed(alice()),
-                1,
-                resources,
-                location,
-                country,
-                city,
-                Vec::new()
-            ),
-            Error::<TestRuntime>::NodeWithTwinIdExists
+        assert_eq!(
+            TfgridModule::nodes_in_bounding_box(30000000, 10000000, 35000000, 15000000),
+            vec![1]
+        );
+        assert_eq!(
+            TfgridModule::nodes_in_bounding_box(-20000000, 10000000, 0, 15000000).len(),
+            0
+        );
+        // box crossing the antimeridian
+        assert_eq!(
+            TfgridModule::nodes_in_bounding_box(-20000000, 179000000, 0, -179000000),
+            vec![2]
         );
     });
 }
@@ -895,32 +2355,32 @@ fn test_create_and_update_policy() {
 
 fn create_entity() {
     let name = "foobar".as_bytes().to_vec();
-    let country = "Belgium".as_bytes().to_vec();
-    let city = "Ghent".as_bytes().to_vec();
+    let country_id = 1;
+    let city_id = 1;
 
-    let signature = sign_create_entity(name.clone(), country.clone(), city.clone());
+    let signature = sign_create_entity(name.clone(), country_id, city_id);
     assert_ok!(TfgridModule::create_entity(
         Origin::signed(alice()),
         test_ed25519(),
         name,
-        country,
-        city,
+        country_id,
+        city_id,
         signature.clone()
     ));
 }
 
 fn create_entity_sr() {
     let name = "foobar".as_bytes().to_vec();
-    let country = "Belgium".as_bytes().to_vec();
-    let city = "Ghent".as_bytes().to_vec();
+    let country_id = 1;
+    let city_id = 1;
 
-    let signature = sign_create_entity_sr(name.clone(), country.clone(), city.clone());
+    let signature = sign_create_entity_sr(name.clone(), country_id, city_id);
     assert_ok!(TfgridModule::create_entity(
         Origin::signed(alice()),
         test_sr25519(),
         name,
-        country,
-        city,
+        country_id,
+        city_id,
         signature.clone()
     ));
 }
@@ -972,8 +2432,8 @@ fn create_farm() {
     let farm_name = "test_farm".as_bytes().to_vec();
     let mut pub_ips = Vec::new();
     pub_ips.push(super::types::PublicIP {
-        ip: "1.1.1.0".as_bytes().to_vec(),
-        gateway: "1.1.1.1".as_bytes().to_vec(),
+        ip: "185.206.122.33/24".as_bytes().to_vec(),
+        gateway: "185.206.122.1".as_bytes().to_vec(),
         contract_id: 0,
     });
     assert_ok!(TfgridModule::create_farm(
@@ -984,8 +2444,8 @@ fn create_farm() {
 }
 
 fn create_node() {
-    let country = "Belgium".as_bytes().to_vec();
-    let city = "Ghent".as_bytes().to_vec();
+    let country_id = 1;
+    let city_id = 1;
 
     // random location
     let location = super::types::Location {
@@ -1005,15 +2465,15 @@ fn create_node() {
         1,
         resources,
         location,
-        country,
-        city,
+        country_id,
+        city_id,
         Vec::new()
     ));
 }
 
 fn create_node_bob() {
-    let country = "Belgium".as_bytes().to_vec();
-    let city = "Ghent".as_bytes().to_vec();
+    let country_id = 1;
+    let city_id = 1;
 
     // random location
     let location = super::types::Location {
@@ -1033,8 +2493,25 @@ fn create_node_bob() {
         1,
         resources,
         location,
-        country,
-        city,
+        country_id,
+        city_id,
         Vec::new()
     ));
 }
+
+fn create_gateway() {
+    let location = super::types::Location {
+        longitude: "12.233213231".as_bytes().to_vec(),
+        latitude: "32.323112123".as_bytes().to_vec(),
+    };
+
+    assert_ok!(TfgridModule::create_gateway(
+        Origin::signed(alice()),
+        1,
+        location,
+        1,
+        1,
+        "some_pub_key".as_bytes().to_vec(),
+        test_ed25519()
+    ));
+}
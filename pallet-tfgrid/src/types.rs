@@ -7,6 +7,9 @@ pub enum StorageVersion {
     V1Struct,
     V2Struct,
     V3Struct,
+    // nodes and entities reference countries and cities by id, node locations are numeric
+    // coordinates and farms have public ipv6 prefixes
+    V4Struct,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Encode, Decode, Default)]
@@ -15,8 +18,8 @@ pub struct Entity<AccountId> {
     pub id: u32,
     pub name: Vec<u8>,
     pub account_id: AccountId,
-    pub country: Vec<u8>,
-    pub city: Vec<u8>,
+    pub country_id: u32,
+    pub city_id: u32,
 }
 
 //digital twin
@@ -32,6 +35,26 @@ pub struct Twin<AccountId> {
     pub entities: Vec<EntityProof>,
 }
 
+// Permissions a twin can delegate to another account
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug, Copy)]
+pub enum DelegatePermission {
+    Contracts,
+    FarmManagement,
+    NodeManagement,
+}
+
+impl DelegatePermission {
+    // the number of permissions that exist, a delegation holds each at most once
+    pub const COUNT: usize = 3;
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct Delegation {
+    pub permissions: Vec<DelegatePermission>,
+    // timestamp in seconds from which the delegation is no longer valid, none if it does not expire
+    pub expires_at: Option<u64>,
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct Farm {
     pub version: u32,
@@ -41,6 +64,7 @@ pub struct Farm {
     pub pricing_policy_id: u32,
     pub certification_type: CertificationType,
     pub public_ips: Vec<PublicIP>,
+    pub public_ipv6_prefixes: Vec<PublicIPv6Prefix>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
@@ -50,9 +74,9 @@ pub struct Node {
     pub farm_id: u32,
     pub twin_id: u32,
     pub resources: Resources,
-    pub location: Location,
-    pub country: Vec<u8>,
-    pub city: Vec<u8>,
+    pub location: Coordinates,
+    pub country_id: u32,
+    pub city_id: u32,
     // optional public config
     pub public_config: Option<PublicConfig>,
     pub created: u64,
@@ -61,6 +85,41 @@ pub struct Node {
     pub certification_type: CertificationType,
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Debug, Copy, Default)]
+pub enum NodeStatus {
+    Up,
+    #[default]
+    Down,
+    Standby,
+    Decommissioned,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct NodeAvailability {
+    pub status: NodeStatus,
+    // timestamp in seconds of the last uptime report of the node
+    pub last_seen: u64,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct NodeUptimeTracker {
+    // uptime in seconds as last reported by the node
+    pub last_uptime: u64,
+    // timestamp in seconds of the last uptime report of the node
+    pub last_report: u64,
+}
+
+// Uptime accounted for a node within a single uptime period
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct UptimeBucket {
+    // index of the period (timestamp / period length)
+    pub period: u64,
+    // number of seconds the node was up during the period
+    pub uptime: u64,
+    // number of reboots detected during the period
+    pub reboots: u32,
+}
+
 pub type IP = Vec<u8>;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
@@ -72,11 +131,357 @@ pub struct Interface {
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct PublicIP {
+    // ipv4 address with the prefix length of its subnet, eg. 185.206.122.33/24
     pub ip: Vec<u8>,
+    // ipv4 address of the gateway, which has to be in the subnet of the ip
     pub gateway: Vec<u8>,
     pub contract_id: u64,
 }
 
+// A parsed ipv4 address with the prefix length of its subnet
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Ipv4Cidr {
+    pub address: u32,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn parse(value: &[u8]) -> Option<Ipv4Cidr> {
+        let mut parts = value.splitn(2, |c| *c == b'/');
+        let address = parse_ipv4(parts.next().unwrap_or(&[]))?;
+        let prefix = parse_decimal(parts.next()?, 32)?;
+
+        Some(Ipv4Cidr {
+            address,
+            prefix: prefix as u8,
+        })
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        let mask = ipv4_netmask(self.prefix);
+        address & mask == self.address & mask
+    }
+
+    // the network address of the subnet
+    pub fn first(&self) -> u32 {
+        self.address & ipv4_netmask(self.prefix)
+    }
+
+    // the broadcast address of the subnet
+    pub fn last(&self) -> u32 {
+        self.address | !ipv4_netmask(self.prefix)
+    }
+
+    // Formats the cidr as it is stored on chain, eg. 185.206.122.33/24
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut value = Vec::new();
+        for (i, octet) in self.address.to_be_bytes().iter().enumerate() {
+            if i > 0 {
+                value.push(b'.');
+            }
+            push_decimal(&mut value, *octet as u32);
+        }
+        value.push(b'/');
+        push_decimal(&mut value, self.prefix as u32);
+        value
+    }
+}
+
+fn push_decimal(value: &mut Vec<u8>, number: u32) {
+    if number >= 10 {
+        push_decimal(value, number / 10);
+    }
+    value.push(b'0' + (number % 10) as u8);
+}
+
+// Parses a dotted decimal ipv4 address (eg. 185.206.122.1)
+pub fn parse_ipv4(value: &[u8]) -> Option<u32> {
+    let mut address: u32 = 0;
+    let mut octets = 0;
+    for octet in value.split(|c| *c == b'.') {
+        address = (address << 8) | parse_decimal(octet, 255)?;
+        octets += 1;
+    }
+
+    if octets != 4 {
+        return None;
+    }
+    Some(address)
+}
+
+// Parses a decimal number of at most 3 digits without leading zeroes
+fn parse_decimal(value: &[u8], max: u32) -> Option<u32> {
+    if value.is_empty() || value.len() > 3 || (value.len() > 1 && value[0] == b'0') {
+        return None;
+    }
+
+    let mut number: u32 = 0;
+    for c in value {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        number = number * 10 + (c - b'0') as u32;
+    }
+
+    if number > max {
+        return None;
+    }
+    Some(number)
+}
+
+fn ipv4_netmask(prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix as u32)
+    }
+}
+
+const fn ipv4(a: u8, b: u8, c: u8, d: u8) -> u32 {
+    (a as u32) << 24 | (b as u32) << 16 | (c as u32) << 8 | d as u32
+}
+
+// Private and special purpose ranges (RFC 6890) which can't be used as public ip
+const RESERVED_IPV4_RANGES: [(u32, u8); 14] = [
+    (ipv4(0, 0, 0, 0), 8),
+    (ipv4(10, 0, 0, 0), 8),
+    (ipv4(100, 64, 0, 0), 10),
+    (ipv4(127, 0, 0, 0), 8),
+    (ipv4(169, 254, 0, 0), 16),
+    (ipv4(172, 16, 0, 0), 12),
+    (ipv4(192, 0, 0, 0), 24),
+    (ipv4(192, 0, 2, 0), 24),
+    (ipv4(192, 168, 0, 0), 16),
+    (ipv4(198, 18, 0, 0), 15),
+    (ipv4(198, 51, 100, 0), 24),
+    (ipv4(203, 0, 113, 0), 24),
+    // multicast
+    (ipv4(224, 0, 0, 0), 4),
+    // reserved, including the limited broadcast address
+    (ipv4(240, 0, 0, 0), 4),
+];
+
+pub fn is_reserved_ipv4(address: u32) -> bool {
+    RESERVED_IPV4_RANGES
+        .iter()
+        .any(|(network, prefix)| Ipv4Cidr { address: *network, prefix: *prefix }.contains(address))
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct PublicIPv6Prefix {
+    // ipv6 prefix in cidr notation, eg. 2a02:1802:5e::/48
+    pub prefix: Vec<u8>,
+    // ipv6 address of the gateway, which has to be in the prefix
+    pub gateway: Vec<u8>,
+    // parts of the prefix that are reserved by contracts
+    pub allocations: Vec<PublicIPv6Allocation>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct PublicIPv6Allocation {
+    // sub-prefix in cidr notation, a single address has a /128 prefix
+    pub prefix: Vec<u8>,
+    pub gateway: Vec<u8>,
+    pub contract_id: u64,
+}
+
+impl PublicIPv6Prefix {
+    // Reserves the first free block with the given prefix length for a contract, none if the
+    // prefix has no room left. The gateway and the subnet-router anycast address (the first
+    // address of the prefix) are never handed out.
+    pub fn allocate(&mut self, prefix_length: u8, contract_id: u64) -> Option<PublicIPv6Allocation> {
+        let prefix = Ipv6Cidr::parse(&self.prefix)?;
+        let gateway = parse_ipv6(&self.gateway)?;
+        if prefix_length <= prefix.prefix || prefix_length > 128 {
+            return None;
+        }
+
+        let mut reserved: Vec<(u128, u128)> = self
+            .allocations
+            .iter()
+            .filter_map(|allocation| Ipv6Cidr::parse(&allocation.prefix))
+            .map(|allocation| (allocation.first(), allocation.last()))
+            .collect();
+        reserved.push((gateway, gateway));
+        reserved.push((prefix.first(), prefix.first()));
+
+        let size: u128 = 1 << (128 - prefix_length as u32);
+        let mut start = prefix.first();
+        loop {
+            let end = start + (size - 1);
+            if !prefix.contains(end) {
+                return None;
+            }
+            match reserved.iter().find(|(first, last)| *first <= end && start <= *last) {
+                // continue at the first aligned block after the overlapping one
+                Some((_, last)) => start = last.checked_add(size)? & !(size - 1),
+                None => break,
+            }
+        }
+
+        let allocation = PublicIPv6Allocation {
+            prefix: Ipv6Cidr {
+                address: start,
+                prefix: prefix_length,
+            }
+            .to_bytes(),
+            gateway: self.gateway.clone(),
+            contract_id,
+        };
+        self.allocations.push(allocation.clone());
+        Some(allocation)
+    }
+}
+
+// A parsed ipv6 address with the prefix length of its subnet
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Ipv6Cidr {
+    pub address: u128,
+    pub prefix: u8,
+}
+
+impl Ipv6Cidr {
+    pub fn parse(value: &[u8]) -> Option<Ipv6Cidr> {
+        let mut parts = value.splitn(2, |c| *c == b'/');
+        let address = parse_ipv6(parts.next().unwrap_or(&[]))?;
+        let prefix = parse_decimal(parts.next()?, 128)?;
+
+        Some(Ipv6Cidr {
+            address,
+            prefix: prefix as u8,
+        })
+    }
+
+    pub fn contains(&self, address: u128) -> bool {
+        let mask = ipv6_netmask(self.prefix);
+        address & mask == self.address & mask
+    }
+
+    pub fn first(&self) -> u128 {
+        self.address & ipv6_netmask(self.prefix)
+    }
+
+    pub fn last(&self) -> u128 {
+        self.address | !ipv6_netmask(self.prefix)
+    }
+
+    // Formats the cidr in its canonical form, eg. 2a02:1802:5e::/48
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut value = format_ipv6(self.address);
+        value.push(b'/');
+        push_decimal(&mut value, self.prefix as u32);
+        value
+    }
+}
+
+// Formats an ipv6 address in its canonical form (RFC 5952), eg. 2a02:1802:5e::1
+pub fn format_ipv6(address: u128) -> Vec<u8> {
+    let groups: Vec<u16> = (0..8).map(|i| (address >> (112 - 16 * i)) as u16).collect();
+
+    // the longest run of at least two zero groups is compressed
+    let (mut run_start, mut run_len) = (8, 0);
+    let mut i = 0;
+    while i < 8 {
+        let len = groups[i..].iter().take_while(|group| **group == 0).count();
+        if len > run_len && len > 1 {
+            run_start = i;
+            run_len = len;
+        }
+        i += len.max(1);
+    }
+
+    let mut value = Vec::new();
+    let mut i = 0;
+    while i < 8 {
+        if i == run_start {
+            value.extend_from_slice(b"::");
+            i += run_len;
+            continue;
+        }
+        if i > 0 && i != run_start + run_len {
+            value.push(b':');
+        }
+        push_hex(&mut value, groups[i]);
+        i += 1;
+    }
+    value
+}
+
+// Parses an ipv6 address (eg. 2a02:1802:5e::1), embedded ipv4 addresses are not supported
+pub fn parse_ipv6(value: &[u8]) -> Option<u128> {
+    let separator = value.windows(2).position(|window| window == b"::");
+    let (head, tail) = match separator {
+        Some(index) => (&value[..index], Some(&value[index + 2..])),
+        None => (value, None),
+    };
+
+    let mut groups = parse_ipv6_groups(head)?;
+    match tail {
+        Some(tail) => {
+            let tail = parse_ipv6_groups(tail)?;
+            if groups.len() + tail.len() > 7 {
+                return None;
+            }
+            groups.resize(8 - tail.len(), 0);
+            groups.extend(tail);
+        }
+        None => {
+            if groups.len() != 8 {
+                return None;
+            }
+        }
+    }
+
+    Some(groups.iter().fold(0, |address, group| (address << 16) | *group as u128))
+}
+
+fn parse_ipv6_groups(value: &[u8]) -> Option<Vec<u16>> {
+    if value.is_empty() {
+        return Some(Vec::new());
+    }
+
+    let mut groups = Vec::new();
+    for group in value.split(|c| *c == b':') {
+        if group.is_empty() || group.len() > 4 {
+            return None;
+        }
+        let mut number: u16 = 0;
+        for c in group {
+            number = (number << 4) | (*c as char).to_digit(16)? as u16;
+        }
+        groups.push(number);
+    }
+    Some(groups)
+}
+
+fn push_hex(value: &mut Vec<u8>, number: u16) {
+    if number >= 16 {
+        push_hex(value, number / 16);
+    }
+    value.push(b"0123456789abcdef"[(number % 16) as usize]);
+}
+
+fn ipv6_netmask(prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        u128::MAX << (128 - prefix as u32)
+    }
+}
+
+// Only global unicast addresses (2000::/3) can be used, except for the documentation prefix
+pub fn is_reserved_ipv6(address: u128) -> bool {
+    let global_unicast = Ipv6Cidr {
+        address: 0x2000 << 112,
+        prefix: 3,
+    };
+    let documentation = Ipv6Cidr {
+        address: 0x2001_0db8 << 96,
+        prefix: 32,
+    };
+    !global_unicast.contains(address) || documentation.contains(address)
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct PublicConfig {
     pub ipv4: Vec<u8>,
@@ -91,7 +496,7 @@ pub struct Gateway<AccountId> {
     pub version: u32,
     pub id: u32,
     pub farm_id: u32,
-    pub location: Location,
+    pub location: Coordinates,
     pub country_id: u32,
     pub city_id: u32,
     pub pub_key: Vec<u8>,
@@ -112,13 +517,98 @@ pub struct Resources {
     pub mru: u64,
 }
 
-// Store Location long and lat as string
+// Location long and lat as passed to the extrinsics, as decimal degree strings (eg. "-12.2332")
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct Location {
     pub longitude: Vec<u8>,
     pub latitude: Vec<u8>,
 }
 
+// Number of decimals of a degree kept when storing coordinates
+pub const COORDINATE_DECIMALS: u32 = 6;
+
+impl Location {
+    // Parse the latitude / longitude into millionths of a degree, none if the value is
+    // not a valid decimal degree or is out of range
+    pub fn parse_latitude(&self) -> Option<i32> {
+        parse_degrees(&self.latitude, 90)
+    }
+
+    pub fn parse_longitude(&self) -> Option<i32> {
+        parse_degrees(&self.longitude, 180)
+    }
+}
+
+// Parses a decimal degree string into millionths of a degree, extra decimals are truncated
+fn parse_degrees(value: &[u8], max_degrees: i64) -> Option<i32> {
+    let (negative, digits) = match value.first() {
+        Some(b'-') => (true, &value[1..]),
+        Some(b'+') => (false, &value[1..]),
+        _ => (false, value),
+    };
+
+    let mut parts = digits.splitn(2, |c| *c == b'.');
+    let integer = parts.next().unwrap_or(&[]);
+    let fraction = parts.next();
+
+    if integer.is_empty() || integer.len() > 3 {
+        return None;
+    }
+    if let Some(fraction) = fraction {
+        if fraction.is_empty() {
+            return None;
+        }
+    }
+
+    let mut value: i64 = 0;
+    for c in integer {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        value = value * 10 + (c - b'0') as i64;
+    }
+
+    let mut decimals = 0;
+    for c in fraction.unwrap_or(&[]) {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        if decimals < COORDINATE_DECIMALS {
+            value = value * 10 + (c - b'0') as i64;
+            decimals += 1;
+        }
+    }
+    value *= 10_i64.pow(COORDINATE_DECIMALS - decimals);
+
+    if value > max_degrees * 10_i64.pow(COORDINATE_DECIMALS) {
+        return None;
+    }
+
+    Some(if negative { -value } else { value } as i32)
+}
+
+// Coordinates stored on chain, in millionths of a degree
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug, Copy)]
+pub struct Coordinates {
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct Country {
+    pub id: u32,
+    pub name: Vec<u8>,
+    // ISO 3166-1 alpha-2 code, empty for countries created by the v2 migration
+    pub code: Vec<u8>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+pub struct City {
+    pub id: u32,
+    pub country_id: u32,
+    pub name: Vec<u8>,
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
 pub struct PricingPolicy<AccountId> {
     pub version: u32,
@@ -232,4 +722,48 @@ pub struct TermsAndConditions<AccountId> {
     pub timestamp: u64,
     pub document_link: Vec<u8>,
     pub document_hash: Vec<u8>
-}
\ No newline at end of file
+}
+
+// Storage layouts of previous versions, used by the storage migrations
+pub mod deprecated {
+    use codec::{Decode, Encode};
+    use frame_support::traits::Vec;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Encode, Decode, Default)]
+    pub struct EntityV1<AccountId> {
+        pub version: u32,
+        pub id: u32,
+        pub name: Vec<u8>,
+        pub account_id: AccountId,
+        pub country: Vec<u8>,
+        pub city: Vec<u8>,
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+    pub struct FarmV1 {
+        pub version: u32,
+        pub id: u32,
+        pub name: Vec<u8>,
+        pub twin_id: u32,
+        pub pricing_policy_id: u32,
+        pub certification_type: super::CertificationType,
+        pub public_ips: Vec<super::PublicIP>,
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Default, Debug)]
+    pub struct NodeV3 {
+        pub version: u32,
+        pub id: u32,
+        pub farm_id: u32,
+        pub twin_id: u32,
+        pub resources: super::Resources,
+        pub location: super::Location,
+        pub country: Vec<u8>,
+        pub city: Vec<u8>,
+        pub public_config: Option<super::PublicConfig>,
+        pub created: u64,
+        pub farming_policy_id: u32,
+        pub interfaces: Vec<super::Interface>,
+        pub certification_type: super::CertificationType,
+    }
+}